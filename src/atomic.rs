@@ -0,0 +1,154 @@
+use crate::core::{parent_dir, Durability};
+use crate::sparse;
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Where a file is written before it is complete: `<name>.tmp` next to it, so
+// the rename that publishes it stays on one file system.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// A file that only appears under its final name once it is complete. Data
+// goes to a temporary file that `commit` syncs (as far as `durability` asks)
+// and renames into place; one dropped without a commit is removed again, so
+// an interrupted write never leaves a truncated file that looks finished.
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: Option<File>,
+    durability: Durability,
+    sparse: bool,
+}
+
+impl AtomicFile {
+    pub(crate) fn create(path: &Path, durability: Durability) -> Result<Self> {
+        let temp_path = temp_path(path);
+        let file = File::create(&temp_path)
+            .context(format!("Failed to create temporary file: {:?}", temp_path))?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            temp_path,
+            file: Some(file),
+            durability,
+            sparse: false,
+        })
+    }
+
+    // Leaves blocks of zeros as holes instead of writing them.
+    pub(crate) fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    pub(crate) fn commit(mut self) -> Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+
+        if self.sparse {
+            sparse::finish_sparse(&mut file)
+                .context(format!("Failed to set file length: {:?}", self.temp_path))?;
+        }
+
+        if self.durability != Durability::None {
+            file.sync_all()
+                .context(format!("Failed to sync file: {:?}", self.temp_path))?;
+        }
+        // Windows cannot rename a file that is still open.
+        drop(file);
+
+        fs::rename(&self.temp_path, &self.path).context(format!(
+            "Failed to rename {:?} to {:?}",
+            self.temp_path, self.path
+        ))?;
+
+        if self.durability == Durability::Directories {
+            sync_dir(parent_dir(&self.path))?;
+        }
+        Ok(())
+    }
+
+    fn file(&mut self) -> io::Result<&mut File> {
+        self.file
+            .as_mut()
+            .ok_or_else(|| io::Error::other("file already committed"))
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.sparse {
+            sparse::write_sparse(self.file()?, buf)?;
+            return Ok(buf.len());
+        }
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file()?.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+// Makes the entries of `dir` (new names, renames) survive a power loss.
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .context(format!("Failed to sync directory: {:?}", dir))
+}
+
+// Directories cannot be opened as files here; renames are as durable as the
+// file system makes them.
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+// Drains a buffered writer and commits the file underneath it.
+pub(crate) fn commit_buffered(writer: BufWriter<AtomicFile>) -> Result<()> {
+    writer
+        .into_inner()
+        .map_err(|e| e.into_error())
+        .context("Failed to flush file")?
+        .commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_appears_only_on_commit() {
+        let path = Path::new("test_atomic.out");
+        let mut file = AtomicFile::create(path, Durability::Directories).unwrap();
+        file.write_all(b"complete").unwrap();
+        assert!(!path.exists());
+        assert!(Path::new("test_atomic.out.tmp").exists());
+        file.commit().unwrap();
+        assert_eq!(fs::read(path).unwrap(), b"complete");
+        assert!(!Path::new("test_atomic.out.tmp").exists());
+
+        // An abandoned write leaves the committed file alone.
+        let mut file = AtomicFile::create(path, Durability::Directories).unwrap();
+        file.write_all(b"trunc").unwrap();
+        drop(file);
+        assert_eq!(fs::read(path).unwrap(), b"complete");
+        assert!(!Path::new("test_atomic.out.tmp").exists());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,557 @@
+use crate::tar_split::TarCutter;
+use anyhow::{Context, Result};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SCAN_BLOCK_SIZE: usize = 64 * 1024;
+
+// Parses a delimiter given on the command line, accepting `\0`, `\n`, `\r`,
+// `\t`, `\\` and `\xNN` escapes.
+pub fn unescape_delimiter(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('0') => bytes.push(0),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .context(format!("Invalid \\x escape in delimiter: {:?}", text))?;
+                bytes.push(byte);
+            }
+            _ => anyhow::bail!("Invalid escape in delimiter: {:?}", text),
+        }
+    }
+
+    if bytes.is_empty() {
+        anyhow::bail!("Delimiter must not be empty");
+    }
+
+    Ok(bytes)
+}
+
+// Where a part is allowed to end. Unless noted otherwise the part size is a
+// maximum: a part is cut at the last allowed position that fits. When none
+// fits, `Line` falls back to an exact byte cut, `Tar` continues the member in
+// the next part, and the others fail rather than break a record.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Boundary {
+    #[default]
+    Bytes,
+    Line,
+    // Exactly `count` lines per part; the part size is not used.
+    Lines {
+        count: u64,
+    },
+    // Ends parts between CSV records (respecting quoted newlines) and repeats
+    // the header record at the start of every part after the first.
+    Csv,
+    // Like `Line`, but a record is never cut, so every part stays valid NDJSON.
+    Ndjson,
+    // Ends parts just after the last occurrence of the delimiter.
+    Delimiter {
+        delimiter: Vec<u8>,
+    },
+    // Starts every part after the first at a line matching the pattern.
+    Regex {
+        pattern: String,
+    },
+    // Ends parts between tar members so each part is a usable archive.
+    Tar,
+    // Content-defined chunks (FastCDC) averaging the part size, stored by
+    // hash in a chunk store shared between versions of the file.
+    Cdc,
+}
+
+impl Boundary {
+    pub fn is_bytes(&self) -> bool {
+        *self == Boundary::Bytes
+    }
+
+    pub fn uses_part_size(&self) -> bool {
+        !matches!(self, Boundary::Lines { .. })
+    }
+}
+
+// Where the next part ends, plus any bytes written around the original data
+// that are not part of it (e.g. a repeated CSV header or a tar end marker).
+pub(crate) struct PartCut {
+    pub end: u64,
+    pub prefix: Vec<u8>,
+    pub suffix: Vec<u8>,
+}
+
+// Decides where each part ends. It reads through its own file handle so the
+// stream the parts are copied from stays strictly sequential.
+pub(crate) struct PartCutter {
+    boundary: Boundary,
+    part_size: u64,
+    file_size: u64,
+    scan: BufReader<File>,
+    header: Vec<u8>,
+    regex: Option<Regex>,
+    tar: Option<TarCutter>,
+}
+
+impl PartCutter {
+    pub fn new(input_path: &Path, boundary: &Boundary, part_size: u64) -> Result<Self> {
+        if let Boundary::Lines { count: 0 } = boundary {
+            anyhow::bail!("Line count must be greater than 0");
+        }
+
+        let file = File::open(input_path).context("Failed to open input file")?;
+        let file_size = file
+            .metadata()
+            .context("Failed to get file metadata")?
+            .len();
+
+        let mut cutter = Self {
+            boundary: boundary.clone(),
+            part_size,
+            file_size,
+            scan: BufReader::with_capacity(SCAN_BLOCK_SIZE, file),
+            header: Vec::new(),
+            regex: None,
+            tar: None,
+        };
+
+        match boundary {
+            Boundary::Delimiter { delimiter } if delimiter.is_empty() => {
+                anyhow::bail!("Delimiter must not be empty");
+            }
+            Boundary::Regex { pattern } => {
+                cutter.regex = Some(
+                    Regex::new(pattern)
+                        .context(format!("Invalid boundary pattern: {}", pattern))?,
+                );
+            }
+            Boundary::Tar => cutter.tar = Some(TarCutter::new(part_size, file_size)?),
+            _ => {}
+        }
+
+        if *boundary == Boundary::Csv {
+            let header_end = cutter
+                .csv_record_end(0, file_size, true)?
+                .unwrap_or(file_size);
+            if header_end >= part_size {
+                anyhow::bail!(
+                    "Part size must be larger than the CSV header ({} bytes)",
+                    header_end
+                );
+            }
+            cutter.header = cutter.read_range(0, header_end)?;
+        }
+
+        Ok(cutter)
+    }
+
+    pub fn next_part(&mut self, start: u64) -> Result<PartCut> {
+        if let Some(tar) = &mut self.tar {
+            return tar.next_part(&mut self.scan, start);
+        }
+
+        let prefix = if start > 0 {
+            self.header.clone()
+        } else {
+            Vec::new()
+        };
+        let budget = self.part_size - prefix.len() as u64;
+        let limit = start.saturating_add(budget).min(self.file_size);
+
+        if limit == self.file_size && self.boundary.uses_part_size() {
+            return Ok(PartCut {
+                end: limit,
+                prefix,
+                suffix: Vec::new(),
+            });
+        }
+
+        let end = match self.boundary.clone() {
+            Boundary::Bytes => limit,
+            Boundary::Line => self
+                .rfind_bytes(b"\n", start, limit)?
+                .map_or(limit, |pos| pos + 1),
+            Boundary::Lines { count } => self.skip_lines(start, count)?,
+            Boundary::Ndjson => self
+                .rfind_bytes(b"\n", start, limit)?
+                .map(|pos| pos + 1)
+                .context(format!(
+                    "NDJSON record at offset {} does not fit in a part of {} bytes",
+                    start, self.part_size
+                ))?,
+            Boundary::Csv => self.csv_record_end(start, limit, false)?.context(format!(
+                "CSV record at offset {} does not fit in a part of {} bytes",
+                start, self.part_size
+            ))?,
+            Boundary::Delimiter { delimiter } => self
+                .rfind_bytes(&delimiter, start, limit)?
+                .map(|pos| pos + delimiter.len() as u64)
+                .context(format!(
+                    "No delimiter found within {} bytes of offset {}",
+                    self.part_size, start
+                ))?,
+            Boundary::Tar => unreachable!("tar parts are cut by TarCutter"),
+            Boundary::Cdc => unreachable!("cdc chunks are cut by the cdc module"),
+            Boundary::Regex { .. } => self.last_matching_line(start, limit)?.context(format!(
+                "No line matching the pattern found within {} bytes of offset {}",
+                self.part_size, start
+            ))?,
+        };
+
+        Ok(PartCut {
+            end,
+            prefix,
+            suffix: Vec::new(),
+        })
+    }
+
+    fn read_range(&mut self, start: u64, end: u64) -> Result<Vec<u8>> {
+        let mut bytes = vec![0u8; (end - start) as usize];
+
+        self.scan
+            .seek(SeekFrom::Start(start))
+            .context("Failed to seek in input file")?;
+        self.scan
+            .read_exact(&mut bytes)
+            .context("Failed to read from input file")?;
+
+        Ok(bytes)
+    }
+
+    // Position of the last occurrence of `pattern` lying entirely within
+    // `start..end`, scanning backwards. Blocks overlap by `pattern.len() - 1`
+    // bytes so occurrences straddling two blocks are still found.
+    fn rfind_bytes(&mut self, pattern: &[u8], start: u64, end: u64) -> Result<Option<u64>> {
+        let overlap = pattern.len() as u64 - 1;
+        let mut block = vec![0u8; SCAN_BLOCK_SIZE + overlap as usize];
+        let mut block_end = end;
+
+        while block_end >= start + pattern.len() as u64 {
+            let block_start = block_end
+                .saturating_sub(SCAN_BLOCK_SIZE as u64 + overlap)
+                .max(start);
+            let len = (block_end - block_start) as usize;
+
+            self.scan
+                .seek(SeekFrom::Start(block_start))
+                .context("Failed to seek in input file")?;
+            self.scan
+                .read_exact(&mut block[..len])
+                .context("Failed to read from input file")?;
+
+            if let Some(pos) = block[..len]
+                .windows(pattern.len())
+                .rposition(|window| window == pattern)
+            {
+                return Ok(Some(block_start + pos as u64));
+            }
+
+            if block_start == start {
+                break;
+            }
+            block_end = block_start + overlap;
+        }
+
+        Ok(None)
+    }
+
+    // Start of the last line within `start..=limit` (other than the one at
+    // `start`) that matches the regex, so the next part begins with it.
+    fn last_matching_line(&mut self, start: u64, limit: u64) -> Result<Option<u64>> {
+        let regex = self
+            .regex
+            .clone()
+            .context("Regex boundary used without a pattern")?;
+
+        self.scan
+            .seek(SeekFrom::Start(start))
+            .context("Failed to seek in input file")?;
+
+        let mut line = Vec::new();
+        let mut line_start = start;
+        let mut last_match = None;
+
+        while line_start <= limit {
+            line.clear();
+            // A line starting by `limit` is read up to a scan block past it,
+            // never without bound however long it is.
+            let window = limit - line_start + 1 + SCAN_BLOCK_SIZE as u64;
+            let bytes_read = (&mut self.scan)
+                .take(window)
+                .read_until(b'\n', &mut line)
+                .context("Failed to read from input file")?;
+
+            if bytes_read == 0 {
+                break;
+            }
+
+            // A line cut off there is not matched: the rest of it might
+            // decide whether it matches.
+            let complete = line.ends_with(b"\n") || (bytes_read as u64) < window;
+            let text = line.strip_suffix(b"\n").unwrap_or(&line);
+            let text = text.strip_suffix(b"\r").unwrap_or(text);
+            if line_start > start && complete && regex.is_match(text) {
+                last_match = Some(line_start);
+            }
+
+            line_start += bytes_read as u64;
+        }
+
+        Ok(last_match)
+    }
+
+    // End of the last complete CSV record within `start..limit` (or the first
+    // one, with `first`). Parts always start on a record boundary, so the scan
+    // starts outside quotes; a doubled `""` toggles twice and cancels out.
+    fn csv_record_end(&mut self, start: u64, limit: u64, first: bool) -> Result<Option<u64>> {
+        self.scan
+            .seek(SeekFrom::Start(start))
+            .context("Failed to seek in input file")?;
+
+        let mut position = start;
+        let mut in_quotes = false;
+        let mut record_end = None;
+
+        while position < limit {
+            let block = self
+                .scan
+                .fill_buf()
+                .context("Failed to read from input file")?;
+
+            if block.is_empty() {
+                break;
+            }
+
+            let len = block.len().min((limit - position) as usize);
+            for (i, &b) in block[..len].iter().enumerate() {
+                match b {
+                    b'"' => in_quotes = !in_quotes,
+                    b'\n' if !in_quotes => {
+                        record_end = Some(position + i as u64 + 1);
+                        if first {
+                            return Ok(record_end);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            position += len as u64;
+            self.scan.consume(len);
+        }
+
+        Ok(record_end)
+    }
+
+    // Offset just past the `count`-th newline after `start`, or the end of file.
+    fn skip_lines(&mut self, start: u64, count: u64) -> Result<u64> {
+        self.scan
+            .seek(SeekFrom::Start(start))
+            .context("Failed to seek in input file")?;
+
+        let mut position = start;
+        let mut remaining = count;
+
+        loop {
+            let block = self
+                .scan
+                .fill_buf()
+                .context("Failed to read from input file")?;
+
+            if block.is_empty() {
+                return Ok(self.file_size);
+            }
+
+            for (i, &b) in block.iter().enumerate() {
+                if b == b'\n' {
+                    remaining -= 1;
+                    if remaining == 0 {
+                        return Ok(position + i as u64 + 1);
+                    }
+                }
+            }
+
+            let len = block.len();
+            position += len as u64;
+            self.scan.consume(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn part_ends(path: &str, boundary: Boundary, part_size: u64) -> Vec<u64> {
+        let mut cutter = PartCutter::new(Path::new(path), &boundary, part_size).unwrap();
+        let file_size = fs::metadata(path).unwrap().len();
+        let mut ends = Vec::new();
+        let mut start = 0;
+
+        while start < file_size {
+            start = cutter.next_part(start).unwrap().end;
+            ends.push(start);
+        }
+
+        ends
+    }
+
+    #[test]
+    fn test_line_boundary_ends_parts_at_newlines() {
+        let path = "test_boundary_line.log";
+        fs::write(path, b"aaaa\nbbbb\ncc\ndddddddddd\ne").unwrap();
+
+        // The 10-byte line cannot fit with its newline, so it is cut hard.
+        assert_eq!(part_ends(path, Boundary::Line, 10), vec![10, 13, 23, 25]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lines_boundary_counts_lines() {
+        let path = "test_boundary_lines.log";
+        fs::write(path, b"1\n22\n333\n4444\n55555").unwrap();
+
+        assert_eq!(
+            part_ends(path, Boundary::Lines { count: 2 }, 0),
+            vec![5, 14, 19]
+        );
+        assert!(PartCutter::new(Path::new(path), &Boundary::Lines { count: 0 }, 1).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_csv_boundary_keeps_quoted_newlines_and_repeats_header() {
+        let path = "test_boundary.csv";
+        fs::write(
+            path,
+            b"id,note\n1,\"multi\nline\"\n2,plain\n3,\"a \"\"b\"\"\"\n",
+        )
+        .unwrap();
+
+        let mut cutter = PartCutter::new(Path::new(path), &Boundary::Csv, 26).unwrap();
+        let first = cutter.next_part(0).unwrap();
+        assert_eq!(first.end, 23);
+        assert!(first.prefix.is_empty());
+
+        let second = cutter.next_part(first.end).unwrap();
+        assert_eq!(second.prefix, b"id,note\n");
+        assert_eq!(second.end, 31);
+
+        // A record longer than the part size cannot be placed anywhere.
+        assert!(PartCutter::new(Path::new(path), &Boundary::Csv, 12)
+            .unwrap()
+            .next_part(8)
+            .is_err());
+        assert!(PartCutter::new(Path::new(path), &Boundary::Csv, 8).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ndjson_boundary_never_cuts_records() {
+        let path = "test_boundary.ndjson";
+        fs::write(path, b"{\"a\":1}\n{\"b\":2}\n{\"long\":\"xxxxxxxxxx\"}\n").unwrap();
+
+        assert_eq!(part_ends(path, Boundary::Ndjson, 22), vec![16, 38]);
+        assert!(PartCutter::new(Path::new(path), &Boundary::Ndjson, 10)
+            .unwrap()
+            .next_part(16)
+            .is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_delimiter_boundary_handles_multibyte_delimiters() {
+        let path = "test_boundary_delimiter.bin";
+        fs::write(path, b"rec1\0rec22\0rec333\0r4").unwrap();
+
+        let nul = Boundary::Delimiter {
+            delimiter: unescape_delimiter("\\0").unwrap(),
+        };
+        assert_eq!(part_ends(path, nul, 12), vec![11, 20]);
+
+        fs::write(path, b"a--END--bb--END--c").unwrap();
+        let marker = Boundary::Delimiter {
+            delimiter: b"--END--".to_vec(),
+        };
+        assert_eq!(part_ends(path, marker.clone(), 17), vec![17, 18]);
+
+        // The only delimiter straddles two scan blocks.
+        let mut data = b"--END--".to_vec();
+        data.resize(SCAN_BLOCK_SIZE + 10, b'x');
+        data.push(b'y');
+        fs::write(path, &data).unwrap();
+        assert_eq!(
+            part_ends(path, marker, SCAN_BLOCK_SIZE as u64 + 10),
+            vec![7, data.len() as u64]
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_regex_boundary_starts_parts_at_matching_lines() {
+        let path = "test_boundary.vcf";
+        fs::write(
+            path,
+            b"BEGIN:VCARD\nFN:A\nEND:VCARD\nBEGIN:VCARD\nFN:B\nEND:VCARD\nBEGIN:VCARD\nFN:C\nEND:VCARD\n",
+        )
+        .unwrap();
+
+        let boundary = Boundary::Regex {
+            pattern: "^BEGIN:VCARD".to_string(),
+        };
+        assert_eq!(part_ends(path, boundary, 60), vec![54, 81]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_regex_boundary_ignores_lines_too_long_to_read() {
+        let path = "test_boundary_cut_line.txt";
+        let mut data = b"B\nB\n".to_vec();
+        data.resize(data.len() + SCAN_BLOCK_SIZE + 1, b'B');
+        data.push(b'\n');
+        fs::write(path, &data).unwrap();
+
+        // The long line is only read in part, so it must not match `^B+$`.
+        let boundary = Boundary::Regex {
+            pattern: "^B+$".to_string(),
+        };
+        let mut cutter = PartCutter::new(Path::new(path), &boundary, 4).unwrap();
+        assert_eq!(cutter.next_part(0).unwrap().end, 2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_unescape_delimiter() {
+        assert_eq!(unescape_delimiter("\\0").unwrap(), vec![0]);
+        assert_eq!(unescape_delimiter("\\r\\n").unwrap(), b"\r\n");
+        assert_eq!(unescape_delimiter("--\\x1e").unwrap(), b"--\x1e");
+        assert!(unescape_delimiter("\\q").is_err());
+        assert!(unescape_delimiter("\\x1").is_err());
+        assert!(unescape_delimiter("").is_err());
+    }
+}
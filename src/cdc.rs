@@ -0,0 +1,249 @@
+use crate::atomic::AtomicFile;
+use crate::boundary::Boundary;
+use crate::core::{
+    civil_from_unix, format_bytes, Durability, ProgressInfo, SplitOptions, BUFFER_SIZE,
+};
+use crate::manifest::{hex_digest, Manifest, PartEntry, MANIFEST_VERSION};
+use crate::metadata::FileMetadata;
+use anyhow::{Context, Result};
+use fastcdc::v2020::{StreamCDC, AVERAGE_MAX, AVERAGE_MIN};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const CHUNK_DIR: &str = "chunks";
+
+pub struct StoreReport {
+    pub manifest_path: PathBuf,
+    pub chunks: Vec<PathBuf>,
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+}
+
+// Cuts the file into content-defined chunks averaging `part_size` bytes, so
+// an insertion or deletion only changes the chunks around it. Chunks are
+// stored as `<store>/chunks/<ab>/<sha256>` and only written if the store
+// doesn't have them yet; each run adds a manifest for this version of the
+// file (`<store>/<name>.<timestamp>.manifest`) that `join` rebuilds it from.
+pub fn split_to_store<F>(
+    input_path: &Path,
+    store: &Path,
+    options: &SplitOptions,
+    mut progress_callback: F,
+) -> Result<StoreReport>
+where
+    F: FnMut(ProgressInfo),
+{
+    let avg_size = options.part_size;
+    if !(AVERAGE_MIN as u64..=AVERAGE_MAX as u64).contains(&avg_size) {
+        anyhow::bail!(
+            "Average chunk size must be between {} and {}",
+            format_bytes(AVERAGE_MIN as u64),
+            format_bytes(AVERAGE_MAX as u64)
+        );
+    }
+
+    if options.compression.is_some() {
+        anyhow::bail!("Compression is not supported for content-defined chunks");
+    }
+
+    let file = File::open(input_path).context("Failed to open input file")?;
+    let file_size = file
+        .metadata()
+        .context("Failed to get file metadata")?
+        .len();
+
+    fs::create_dir_all(store.join(CHUNK_DIR))
+        .context(format!("Failed to create chunk store: {:?}", store))?;
+
+    let avg_size = avg_size as u32;
+    let chunker = StreamCDC::new(
+        BufReader::with_capacity(BUFFER_SIZE, file),
+        avg_size / 4,
+        avg_size,
+        avg_size * 4,
+    );
+
+    let mut parts = Vec::new();
+    let mut chunks = Vec::new();
+    let mut new_chunks = 0;
+    let mut new_bytes = 0u64;
+
+    for chunk in chunker {
+        let chunk = chunk
+            .map_err(std::io::Error::from)
+            .context("Failed to read from input file")?;
+        options.rate_limit.take(chunk.length as u64);
+
+        let hash = hex_digest(&Sha256::digest(&chunk.data));
+        let name = format!("{}/{}/{}", CHUNK_DIR, &hash[..2], hash);
+        let chunk_path = store.join(&name);
+
+        if !chunk_path.exists() {
+            write_chunk(&chunk_path, &chunk.data, options.durability)?;
+            new_chunks += 1;
+            new_bytes += chunk.length as u64;
+        }
+
+        parts.push(PartEntry {
+            name,
+            offset: chunk.offset,
+            length: chunk.length as u64,
+            prefix_len: 0,
+            suffix_len: 0,
+            sha256: Some(hash),
+        });
+        chunks.push(chunk_path);
+
+        progress_callback(ProgressInfo {
+            current_bytes: chunk.offset + chunk.length as u64,
+            total_bytes: file_size,
+            current_part: chunks.len(),
+            total_parts: 0,
+            message: format!("Chunking ({} new)", new_chunks),
+        });
+    }
+
+    let file_name = input_path
+        .file_name()
+        .context("Invalid file name")?
+        .to_string_lossy()
+        .to_string();
+    let manifest_path = version_manifest_path(store, &file_name);
+
+    Manifest {
+        version: MANIFEST_VERSION,
+        file_name,
+        file_size,
+        part_size: options.part_size,
+        boundary: Boundary::Cdc,
+        compression: None,
+        directory: false,
+        metadata: Some(FileMetadata::capture(input_path)?),
+        sparse: false,
+        parts,
+    }
+    .save(&manifest_path, options.durability)?;
+
+    progress_callback(ProgressInfo {
+        current_bytes: file_size,
+        total_bytes: file_size,
+        current_part: chunks.len(),
+        total_parts: chunks.len(),
+        message: "Split complete!".to_string(),
+    });
+
+    Ok(StoreReport {
+        manifest_path,
+        chunks,
+        new_chunks,
+        new_bytes,
+    })
+}
+
+// Chunks are written under a temporary name first, so an interrupted run never
+// leaves a truncated chunk that a later run would take as already stored.
+fn write_chunk(chunk_path: &Path, data: &[u8], durability: Durability) -> Result<()> {
+    if let Some(dir) = chunk_path.parent() {
+        fs::create_dir_all(dir).context(format!("Failed to create directory: {:?}", dir))?;
+    }
+
+    let mut file = AtomicFile::create(chunk_path, durability)?;
+    file.write_all(data)
+        .context(format!("Failed to write chunk: {:?}", chunk_path))?;
+    file.commit()
+        .context(format!("Failed to write chunk: {:?}", chunk_path))
+}
+
+// `vm.img.20261018T174300Z.manifest`, with a counter added if several
+// versions are stored within the same second.
+fn version_manifest_path(store: &Path, file_name: &str) -> PathBuf {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (year, month, day, hour, minute, second) = civil_from_unix(now);
+    let stamp = format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day, hour, minute, second
+    );
+
+    let mut path = store.join(format!("{}.{}.manifest", file_name, stamp));
+    let mut counter = 2;
+    while path.exists() {
+        path = store.join(format!("{}.{}-{}.manifest", file_name, stamp, counter));
+        counter += 1;
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conflict::ConflictPolicy;
+    use crate::core::{self, JoinOptions};
+
+    fn pseudo_random(len: usize, mut state: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_versions_share_unchanged_chunks() {
+        let store = Path::new("test_cdc_store");
+        let input = Path::new("test_cdc_image.bin");
+        let _ = fs::remove_dir_all(store);
+
+        let version1 = pseudo_random(400_000, 0x9e37_79b9_7f4a_7c15);
+        let mut version2 = version1.clone();
+        version2.splice(200_000..200_000, pseudo_random(1000, 42));
+
+        let options = SplitOptions {
+            boundary: Boundary::Cdc,
+            ..SplitOptions::new(4096)
+        };
+
+        fs::write(input, &version1).unwrap();
+        let first = split_to_store(input, store, &options, |_| {}).unwrap();
+        assert_eq!(first.new_chunks, first.chunks.len());
+
+        fs::write(input, &version2).unwrap();
+        let second = split_to_store(input, store, &options, |_| {}).unwrap();
+        assert_ne!(first.manifest_path, second.manifest_path);
+        assert!(second.new_chunks > 0);
+        assert!(second.new_chunks <= 4, "{} new chunks", second.new_chunks);
+        assert!(second.new_bytes < 40_000);
+
+        let output = Path::new("test_cdc_output.bin");
+        core::join_files(&first.manifest_path, output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), version1);
+        fs::remove_file(output).unwrap();
+        core::join_files(&second.manifest_path, output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), version2);
+
+        // A corrupted chunk of the right length is caught by its hash, and the
+        // output it would have replaced is left alone.
+        let chunk_len = fs::metadata(&second.chunks[0]).unwrap().len() as usize;
+        fs::write(&second.chunks[0], vec![0u8; chunk_len]).unwrap();
+        let overwrite = JoinOptions {
+            on_conflict: ConflictPolicy::Overwrite,
+            ..JoinOptions::default()
+        };
+        assert!(
+            core::join_files_with_options(&second.manifest_path, output, &overwrite, |_| {})
+                .is_err()
+        );
+        assert_eq!(fs::read(output).unwrap(), version2);
+
+        fs::remove_dir_all(store).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Read, Write};
+
+// Each part is written as its own complete gzip member / zstd frame, so a
+// single part can be decompressed on its own (e.g. `zcat file.log.part003`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+pub enum PartEncoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> PartEncoder<W> {
+    pub fn new(inner: W, compression: Option<Compression>) -> Result<Self> {
+        Ok(match compression {
+            None => PartEncoder::Plain(inner),
+            Some(Compression::Gzip) => {
+                PartEncoder::Gzip(GzEncoder::new(inner, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => PartEncoder::Zstd(
+                zstd::Encoder::new(inner, zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .context("Failed to create zstd encoder")?,
+            ),
+        })
+    }
+
+    // Terminates the gzip member / zstd frame and hands back the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            PartEncoder::Plain(inner) => Ok(inner),
+            PartEncoder::Gzip(encoder) => encoder.finish(),
+            PartEncoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for PartEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            PartEncoder::Plain(inner) => inner.write(buf),
+            PartEncoder::Gzip(encoder) => encoder.write(buf),
+            PartEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            PartEncoder::Plain(inner) => inner.flush(),
+            PartEncoder::Gzip(encoder) => encoder.flush(),
+            PartEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+// The most `len` bytes cut into `parts` parts can take once compressed, for
+// data that does not compress at all. Both zlib's deflateBound and zstd's
+// ZSTD_COMPRESSBOUND stay below 1/256 of growth plus a small fixed cost per
+// member or frame.
+pub(crate) fn compressed_size_bound(len: u64, parts: u64) -> u64 {
+    len + len / 256 + parts * 64
+}
+
+pub fn part_decoder<'a, R: BufRead + 'a>(
+    reader: R,
+    compression: Option<Compression>,
+) -> Result<Box<dyn Read + 'a>> {
+    Ok(match compression {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => Box::new(GzDecoder::new(reader)),
+        Some(Compression::Zstd) => {
+            Box::new(zstd::Decoder::with_buffer(reader).context("Failed to create zstd decoder")?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_compressed_size_bound_covers_random_data() {
+        // xorshift, so the data can't compress.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let data: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut encoder = PartEncoder::new(Vec::new(), Some(compression)).unwrap();
+            encoder.write_all(&data).unwrap();
+            let compressed = encoder.finish().unwrap();
+            assert!(compressed.len() as u64 <= compressed_size_bound(data.len() as u64, 1));
+        }
+    }
+
+    fn round_trip(compression: Compression) {
+        let data = b"2024-01-01 INFO started\n".repeat(1000);
+
+        let mut encoder = PartEncoder::new(Vec::new(), Some(compression)).unwrap();
+        encoder.write_all(&data).unwrap();
+        let encoded = encoder.finish().unwrap();
+        assert!(encoded.len() < data.len());
+
+        let mut decoded = Vec::new();
+        part_decoder(BufReader::new(&encoded[..]), Some(compression))
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        round_trip(Compression::Gzip);
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        round_trip(Compression::Zstd);
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// What to do when a split or join would write over files that already exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ConflictPolicy {
+    // Stop before anything is written.
+    #[default]
+    Fail,
+    // Replace the existing files. A split also removes parts of the old set
+    // numbered beyond the new last part, so they cannot leak into a join.
+    Overwrite,
+    // Leave the existing files alone and write nothing.
+    Skip,
+    // Write under the first free name instead (`file (1).bin`).
+    Rename,
+}
+
+// Every file an earlier split named after `base_path` left behind: the
+// manifest and all `<name>.partNNN`, whether or not they are in sequence.
+// For a split ZIP, `base_path` is the `.zip` and its `.zNN` segments count.
+pub fn existing_split_files(base_path: &Path, zip: bool) -> Result<Vec<PathBuf>> {
+    let Some(name) = base_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+    else {
+        return Ok(Vec::new());
+    };
+    let dir = match base_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let stem = name.strip_suffix(".zip").unwrap_or(&name);
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
+        let entry = entry.context(format!("Failed to read directory: {:?}", dir))?;
+        let entry_name = entry.file_name().to_string_lossy().to_string();
+
+        let matches = if zip {
+            entry_name == name || segment_number(&entry_name, stem).is_some()
+        } else {
+            entry_name == format!("{}.manifest", name) || part_number(&entry_name, &name).is_some()
+        };
+        if matches {
+            files.push(base_path.with_file_name(entry_name));
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+// Parts (or ZIP segments) of an old set numbered beyond `part_count`, which a
+// shorter split over the same name would otherwise leave in place.
+pub fn stale_parts(base_path: &Path, zip: bool, part_count: usize) -> Result<Vec<PathBuf>> {
+    let name = base_path
+        .file_name()
+        .context("Invalid file name")?
+        .to_string_lossy()
+        .to_string();
+    let stem = name.strip_suffix(".zip").unwrap_or(&name).to_string();
+
+    // A split ZIP's last segment is the `.zip` itself, so its `.zNN` segments
+    // only go up to `part_count - 1`.
+    let keep = if zip {
+        part_count.saturating_sub(1)
+    } else {
+        part_count
+    } as u32;
+
+    Ok(existing_split_files(base_path, zip)?
+        .into_iter()
+        .filter(|path| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let number = if zip {
+                segment_number(&file_name, &stem)
+            } else {
+                part_number(&file_name, &name)
+            };
+            number.is_some_and(|number| number > keep)
+        })
+        .collect())
+}
+
+// The first of `path`, `name (1).ext`, `name (2).ext`, ... that is not taken.
+pub fn free_path(path: &Path, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !is_taken(path) {
+        return path.to_path_buf();
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // The extension starts at the first dot that does not begin the name, so
+    // `backup.tar.gz` becomes `backup (1).tar.gz`.
+    let split = name
+        .char_indices()
+        .skip(1)
+        .find(|&(_, c)| c == '.')
+        .map_or(name.len(), |(i, _)| i);
+    let (stem, extension) = name.split_at(split);
+
+    (1..)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, extension)))
+        .find(|candidate| !is_taken(candidate))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+// `3` for `<name>.part003`.
+fn part_number(file_name: &str, name: &str) -> Option<u32> {
+    let digits = file_name.strip_prefix(name)?.strip_prefix(".part")?;
+    parse_number(digits, 3)
+}
+
+// `3` for `<stem>.z03`.
+fn segment_number(file_name: &str, stem: &str) -> Option<u32> {
+    let digits = file_name.strip_prefix(stem)?.strip_prefix(".z")?;
+    parse_number(digits, 2)
+}
+
+fn parse_number(digits: &str, min_len: usize) -> Option<u32> {
+    if digits.len() < min_len || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_free_path() {
+        let taken = |path: &Path| {
+            matches!(
+                path.to_str(),
+                Some("out/backup.tar.gz" | "out/backup (1).tar.gz")
+            )
+        };
+        assert_eq!(
+            free_path(Path::new("out/backup.tar.gz"), taken),
+            PathBuf::from("out/backup (2).tar.gz")
+        );
+        assert_eq!(
+            free_path(Path::new("out/other.bin"), taken),
+            PathBuf::from("out/other.bin")
+        );
+        assert_eq!(
+            free_path(Path::new(".hidden"), |p| p == Path::new(".hidden")),
+            PathBuf::from(".hidden (1)")
+        );
+    }
+
+    #[test]
+    fn test_stale_parts() {
+        let dir = Path::new("test_conflict_stale");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        for name in [
+            "data.bin.part001",
+            "data.bin.part002",
+            "data.bin.part003",
+            "data.bin.part1000",
+            "data.bin.manifest",
+            "data.bin.part002.tmp",
+            "data.bin2.part004",
+        ] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        let base = dir.join("data.bin");
+        assert_eq!(existing_split_files(&base, false).unwrap().len(), 5);
+        assert_eq!(
+            stale_parts(&base, false, 2).unwrap(),
+            vec![dir.join("data.bin.part003"), dir.join("data.bin.part1000")]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
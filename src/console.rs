@@ -0,0 +1,307 @@
+use crate::report::{self, OutputFormat};
+use colored::{Color, Colorize};
+use filesplitter::core::{format_bytes, ProgressInfo};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::cell::{Cell, RefCell};
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const RULE: &str = "═══════════════════════════════════════";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    // Errors and warnings only.
+    Quiet,
+    #[default]
+    Normal,
+    // Also part sizes, timings and every progress step.
+    Verbose,
+}
+
+// Everything the CLI commands print goes through a `Console`, so the output
+// format, the verbosity and the terminal decide what is shown, not each
+// command.
+#[derive(Debug, Clone, Copy)]
+pub struct Console {
+    format: OutputFormat,
+    verbosity: Verbosity,
+    progress_events: bool,
+    // stdout and stderr are terminals, so progress bars can redraw in place.
+    interactive: bool,
+    color: bool,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new(OutputFormat::Text, Verbosity::Normal, false)
+    }
+}
+
+impl Console {
+    pub fn new(format: OutputFormat, verbosity: Verbosity, progress_events: bool) -> Self {
+        let interactive = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+        // https://no-color.org: any non-empty NO_COLOR turns colors off.
+        let color = interactive && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty());
+        if !color {
+            colored::control::set_override(false);
+        }
+
+        Self {
+            format,
+            verbosity,
+            progress_events,
+            interactive,
+            color,
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    fn shows(&self, verbosity: Verbosity) -> bool {
+        self.format == OutputFormat::Text && self.verbosity >= verbosity
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.shows(Verbosity::Verbose)
+    }
+
+    pub fn banner(&self) {
+        if !self.shows(Verbosity::Normal) {
+            return;
+        }
+        println!(
+            "\n{}",
+            "╔═══════════════════════════════════════╗".bright_blue()
+        );
+        println!(
+            "{}",
+            "║       FILE SPLITTER & JOINER         ║"
+                .bright_blue()
+                .bold()
+        );
+        println!(
+            "{}",
+            "║         Fast • Efficient • Safe       ║".bright_blue()
+        );
+        println!(
+            "{}",
+            "╚═══════════════════════════════════════╝".bright_blue()
+        );
+    }
+
+    // A heading between two rules; the caller pads it to sit in the middle.
+    pub fn title(&self, title: &str, color: Color) {
+        if !self.shows(Verbosity::Normal) {
+            return;
+        }
+        println!("\n{}", RULE.color(color));
+        println!("{}", title.color(color).bold());
+        println!("{}", RULE.color(color));
+    }
+
+    // "✓ <message> <count>" between two rules.
+    pub fn success(&self, message: &str, count: impl Display, color: Color) {
+        if !self.shows(Verbosity::Normal) {
+            return;
+        }
+        println!("\n{}", RULE.color(color));
+        println!("{} {}", message.green().bold(), count);
+        println!("{}", RULE.color(color));
+    }
+
+    // "<label> <value>", the value colored by the caller.
+    pub fn field(&self, label: &str, value: impl Display) {
+        self.line(format!("{} {}", label.green().bold(), value));
+    }
+
+    pub fn line(&self, text: impl Display) {
+        if self.shows(Verbosity::Normal) {
+            println!("{}", text);
+        }
+    }
+
+    pub fn blank(&self) {
+        self.line("");
+    }
+
+    pub fn detail(&self, text: impl Display) {
+        if self.shows(Verbosity::Verbose) {
+            println!("{}", text);
+        }
+    }
+
+    // Shown even with --quiet.
+    pub fn warning(&self, text: impl Display) {
+        if self.format == OutputFormat::Text {
+            println!("{} {}", "⚠ Warning:".yellow().bold(), text);
+        }
+    }
+
+    pub fn error(&self, error: &anyhow::Error) {
+        match self.format {
+            OutputFormat::Text => {
+                eprintln!("\n{} {}", "✗ Error:".red().bold(), error.to_string().red())
+            }
+            OutputFormat::Json => report::emit(
+                "error",
+                serde_json::json!({ "message": format!("{:#}", error) }),
+            ),
+        }
+    }
+
+    // Numbered part files, with their sizes when verbose.
+    pub fn part_list(&self, title: &str, parts: &[PathBuf]) {
+        if !self.shows(Verbosity::Normal) {
+            return;
+        }
+        println!("\n{}", title.yellow().bold());
+        for (i, part) in parts.iter().enumerate() {
+            let number = format!("[{}]", i + 1).cyan();
+            if self.is_verbose() {
+                let size = std::fs::metadata(part).map_or(0, |m| m.len());
+                println!("  {} {:?} ({})", number, part, format_bytes(size));
+            } else {
+                println!("  {} {:?}", number, part);
+            }
+        }
+        println!();
+    }
+
+    pub fn elapsed(&self, elapsed: Duration) {
+        self.detail(format!(
+            "{} {:.2}s\n",
+            "Elapsed:".green().bold(),
+            elapsed.as_secs_f64()
+        ));
+    }
+
+    // Progress over `total` bytes: a bar on a terminal, a line per tenth of
+    // the way (per step when verbose) otherwise, and events in JSON mode.
+    pub fn progress(&self, total: u64, bar_color: &str) -> Progress {
+        let kind = if self.is_json() {
+            if self.progress_events {
+                ProgressKind::Events
+            } else {
+                ProgressKind::Hidden
+            }
+        } else if self.verbosity == Verbosity::Quiet {
+            ProgressKind::Hidden
+        } else if self.interactive {
+            let template = if self.color {
+                format!("{{spinner:.green}} [{{elapsed_precise}}] [{{bar:50.{}/blue}}] {{bytes}}/{{total_bytes}} ({{percent}}%) {{msg}} ETA: {{eta}}", bar_color)
+            } else {
+                "{spinner} [{elapsed_precise}] [{bar:50}] {bytes}/{total_bytes} ({percent}%) {msg} ETA: {eta}".to_string()
+            };
+            let pb = ProgressBar::new(total);
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(&template)
+                    .unwrap()
+                    .progress_chars("█▓▒░ "),
+            );
+            ProgressKind::Bar(pb)
+        } else {
+            ProgressKind::Lines {
+                tenths: Cell::new(None),
+                message: RefCell::new(String::new()),
+                every_step: self.is_verbose(),
+            }
+        };
+        Progress { kind }
+    }
+}
+
+pub struct Progress {
+    kind: ProgressKind,
+}
+
+enum ProgressKind {
+    Bar(ProgressBar),
+    Lines {
+        // The last tenth of the way a line was printed for.
+        tenths: Cell<Option<u64>>,
+        message: RefCell<String>,
+        every_step: bool,
+    },
+    Events,
+    Hidden,
+}
+
+impl Progress {
+    pub fn update(&self, info: ProgressInfo) {
+        match &self.kind {
+            ProgressKind::Bar(pb) => {
+                pb.set_message(info.message);
+                pb.set_position(info.current_bytes);
+            }
+            ProgressKind::Lines {
+                tenths,
+                message,
+                every_step,
+            } => {
+                // The last line is left to `finish`.
+                let done = info.total_bytes > 0 && info.current_bytes >= info.total_bytes;
+                let tenth = percent(info.current_bytes, info.total_bytes) / 10;
+                let new_step = *message.borrow() != info.message;
+                if !done && (tenths.get() < Some(tenth) || (*every_step && new_step)) {
+                    tenths.set(Some(tenth));
+                    print_line(info.current_bytes, info.total_bytes, &info.message);
+                }
+                if new_step {
+                    *message.borrow_mut() = info.message;
+                }
+            }
+            ProgressKind::Events => report::emit("progress", &info),
+            ProgressKind::Hidden => {}
+        }
+    }
+
+    pub fn finish(&self, message: &str) {
+        match &self.kind {
+            ProgressKind::Bar(pb) => pb.finish_with_message(message.green().to_string()),
+            ProgressKind::Lines { .. } => println!("100% {}", message),
+            ProgressKind::Events | ProgressKind::Hidden => {}
+        }
+    }
+
+    pub fn clear(&self) {
+        if let ProgressKind::Bar(pb) = &self.kind {
+            pb.finish_and_clear();
+        }
+    }
+}
+
+fn percent(current: u64, total: u64) -> u64 {
+    if total == 0 {
+        100
+    } else {
+        (current.min(total) as u128 * 100 / total as u128) as u64
+    }
+}
+
+fn print_line(current: u64, total: u64, message: &str) {
+    println!(
+        "{:>3}% {}/{} {}",
+        percent(current, total),
+        format_bytes(current),
+        format_bytes(total),
+        message
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percent() {
+        assert_eq!(percent(0, 0), 100);
+        assert_eq!(percent(1, 3), 33);
+        assert_eq!(percent(5, 4), 100);
+        assert_eq!(percent(u64::MAX / 2, u64::MAX), 49);
+    }
+}
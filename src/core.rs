@@ -1,11 +1,31 @@
+use crate::atomic::{self, AtomicFile};
+use crate::boundary::{Boundary, PartCutter};
+use crate::cdc;
+use crate::compression::{self, part_decoder, Compression, PartEncoder};
+use crate::conflict::{self, ConflictPolicy};
+use crate::delta::DeltaBase;
+use crate::directory;
+use crate::in_place;
+use crate::manifest::{
+    hex_digest, manifest_path, manifest_path_for_part, Manifest, PartEntry, MANIFEST_VERSION,
+};
+use crate::metadata::FileMetadata;
+use crate::rate_limit::RateLimit;
+use crate::space;
+use crate::sparse::{self, SparseReader};
+use crate::zip_split;
 use anyhow::{Context, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{self, BufReader, BufWriter, Read, Take, Write};
 use std::path::{Path, PathBuf};
 
-const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8MB buffer for fast I/O
+pub(crate) const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8MB buffer for fast I/O
+pub(crate) const PROGRESS_INTERVAL: u64 = 1024 * 1024;
 
 #[allow(dead_code)]
+#[derive(Serialize)]
 pub struct ProgressInfo {
     pub current_bytes: u64,
     pub total_bytes: u64,
@@ -24,19 +44,420 @@ impl ProgressInfo {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    pub part_size: u64,
+    pub boundary: Boundary,
+    pub compression: Option<Compression>,
+    // Write a standard split ZIP (`.z01`, ..., `.zip`) instead of raw parts.
+    pub zip: bool,
+    // Chunk store directory for `Boundary::Cdc`.
+    pub store: Option<PathBuf>,
+    // Manifest of a previous split; unchanged parts are not written again.
+    pub against: Option<PathBuf>,
+    // Skip the free space check done before any part is written.
+    pub ignore_space_check: bool,
+    pub durability: Durability,
+    // What to do about parts left by an earlier split with the same name.
+    pub on_conflict: ConflictPolicy,
+    // Delete the input once the parts are written and read back intact.
+    pub remove_source: bool,
+    // Caps the bytes per second read from the input.
+    pub rate_limit: RateLimit,
+}
+
+impl SplitOptions {
+    pub fn new(part_size: u64) -> Self {
+        Self {
+            part_size,
+            boundary: Boundary::Bytes,
+            compression: None,
+            zip: false,
+            store: None,
+            against: None,
+            ignore_space_check: false,
+            durability: Durability::default(),
+            on_conflict: ConflictPolicy::default(),
+            remove_source: false,
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JoinMode {
+    // Write a new output file and keep the parts.
+    #[default]
+    Copy,
+    // Append the parts onto the first one, deleting them as they are copied.
+    InPlace,
+    // Copy when there is room for the output, otherwise join in place.
+    Auto,
+}
+
+// How far a finished file is pushed towards stable storage before it counts
+// as written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Durability {
+    // Leave writing back to the operating system.
+    None,
+    // fsync each file before it is renamed into place.
+    #[default]
+    Files,
+    // Also fsync the directory after the rename, so the new name survives a
+    // power loss too.
+    Directories,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JoinOptions {
+    pub mode: JoinMode,
+    pub durability: Durability,
+    // What to do when the output already exists.
+    pub on_conflict: ConflictPolicy,
+    // Skip the free space check done before the output is written.
+    pub ignore_space_check: bool,
+    // Leave the output with fresh metadata instead of the original file's
+    // modification time, permissions, ownership and extended attributes.
+    pub skip_metadata: bool,
+    // Delete the parts and their manifest once the output is complete.
+    pub remove_parts: bool,
+    // Caps the bytes per second read from the parts.
+    pub rate_limit: RateLimit,
+}
+
 pub fn split_file<F>(
     input_path: &Path,
     part_size: u64,
-    mut progress_callback: F,
+    progress_callback: F,
 ) -> Result<Vec<PathBuf>>
 where
     F: FnMut(ProgressInfo),
 {
+    split_file_with_options(input_path, &SplitOptions::new(part_size), progress_callback)
+}
+
+pub fn split_file_with_options<F>(
+    input_path: &Path,
+    options: &SplitOptions,
+    progress_callback: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: FnMut(ProgressInfo),
+{
+    split_file_with_report(input_path, options, progress_callback).map(|report| report.parts)
+}
+
+pub struct SplitReport {
+    // The part files written; a delta split leaves out the ones it reused.
+    pub parts: Vec<PathBuf>,
+    // Where the manifest went, which `--on-conflict rename` may have moved
+    // to a new name. A split ZIP has none.
+    pub manifest_path: Option<PathBuf>,
+}
+
+pub fn split_file_with_report<F>(
+    input_path: &Path,
+    options: &SplitOptions,
+    mut progress_callback: F,
+) -> Result<SplitReport>
+where
+    F: FnMut(ProgressInfo),
+{
+    let mut part_size = options.part_size;
+
     if !input_path.exists() {
         anyhow::bail!("Input file does not exist: {:?}", input_path);
     }
 
-    if part_size == 0 {
+    let delta = match &options.against {
+        Some(against) => {
+            if options.zip || input_path.is_dir() || !options.boundary.is_bytes() {
+                anyhow::bail!("Delta splits only support fixed-size parts of a file");
+            }
+            if part_size == 0 {
+                part_size = Manifest::load(against)?.part_size;
+            }
+            Some(DeltaBase::load(
+                against,
+                parent_dir(input_path),
+                part_size,
+                options.compression,
+            )?)
+        }
+        None => None,
+    };
+
+    if options.boundary == Boundary::Cdc {
+        let store = options
+            .store
+            .as_deref()
+            .context("Content-defined chunking needs a chunk store directory")?;
+        // Chunks already in the store are not written again, but the check
+        // cannot know which those are.
+        if !options.ignore_space_check {
+            space::ensure_space(store, sparse::allocated_size(input_path))?;
+        }
+        let report = cdc::split_to_store(input_path, store, options, &mut progress_callback)?;
+        if options.remove_source {
+            remove_split_source(input_path, &report.manifest_path, progress_callback)?;
+        }
+        return Ok(SplitReport {
+            parts: report.chunks,
+            manifest_path: Some(report.manifest_path),
+        });
+    }
+
+    let mut base_path = split_base_path(input_path, options)?;
+    let existing = conflict::existing_split_files(&base_path, options.zip)?;
+    if !existing.is_empty() {
+        match options.on_conflict {
+            ConflictPolicy::Fail => anyhow::bail!(
+                "Parts from an earlier split already exist: {:?}{}",
+                existing[0],
+                more_files(existing.len() - 1)
+            ),
+            ConflictPolicy::Skip => {
+                let manifest = manifest_path(&base_path);
+                return Ok(SplitReport {
+                    parts: existing.into_iter().filter(|p| *p != manifest).collect(),
+                    manifest_path: (!options.zip).then_some(manifest),
+                });
+            }
+            ConflictPolicy::Overwrite => {}
+            ConflictPolicy::Rename => {
+                base_path = conflict::free_path(&base_path, |path| {
+                    conflict::existing_split_files(path, options.zip)
+                        .map_or(true, |files| !files.is_empty())
+                });
+            }
+        }
+    }
+
+    if !options.ignore_space_check {
+        let (space_path, data_size) = if input_path.is_dir() {
+            (
+                directory::archive_path(input_path)?,
+                directory::tree_size(input_path)?,
+            )
+        } else if let Some(delta) = &delta {
+            (
+                input_path.to_path_buf(),
+                delta.changed_bytes(input_path, part_size)?,
+            )
+        } else {
+            // Holes in a sparse input stay holes in the parts.
+            (input_path.to_path_buf(), sparse::allocated_size(input_path))
+        };
+        // Compressed parts are checked against what data that does not
+        // compress at all would take.
+        let required = if options.compression.is_some() {
+            let parts = if part_size > 0 {
+                data_size.div_ceil(part_size)
+            } else {
+                1
+            };
+            compression::compressed_size_bound(data_size, parts)
+        } else {
+            data_size
+        };
+        space::ensure_space(&space_path, required)?;
+    }
+
+    let part_paths = if options.zip {
+        zip_split::split_zip(
+            input_path,
+            &base_path,
+            part_size,
+            options.durability,
+            &options.rate_limit,
+            &mut progress_callback,
+        )?
+    } else if input_path.is_dir() {
+        directory::split_directory(input_path, &base_path, options, &mut progress_callback)?
+    } else {
+        split_into_parts(
+            input_path,
+            &base_path,
+            options,
+            part_size,
+            delta,
+            &mut progress_callback,
+        )?
+    };
+
+    // Parts of an old, longer set would otherwise be picked up by a join. A
+    // delta split only returns the parts it rewrote, so the manifest says
+    // how many the new set has.
+    if options.on_conflict == ConflictPolicy::Overwrite {
+        let part_count = if options.zip {
+            part_paths.len()
+        } else {
+            Manifest::load(&manifest_path(&base_path))?.parts.len()
+        };
+        for stale in conflict::stale_parts(&base_path, options.zip, part_count)? {
+            std::fs::remove_file(&stale)
+                .context(format!("Failed to remove stale part file: {:?}", stale))?;
+        }
+    }
+
+    let manifest = (!options.zip).then(|| manifest_path(&base_path));
+    if options.remove_source {
+        let part_set = manifest.as_deref().unwrap_or(&base_path);
+        remove_split_source(input_path, part_set, progress_callback)?;
+    }
+
+    Ok(SplitReport {
+        parts: part_paths,
+        manifest_path: manifest,
+    })
+}
+
+// Deletes the input of a finished split. `part_set` is the first part or the
+// manifest of the new parts, which are read back and checked against the
+// manifest first, since they are about to become the only copy. A split ZIP
+// has no manifest; its entries are checked against their CRCs instead.
+pub fn remove_split_source<F>(
+    input_path: &Path,
+    part_set: &Path,
+    progress_callback: F,
+) -> Result<()>
+where
+    F: FnMut(ProgressInfo),
+{
+    if part_set
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+    {
+        zip_split::verify_split_zip(part_set, progress_callback)?;
+    } else {
+        verify_parts(part_set, progress_callback)?;
+    }
+
+    if input_path.is_dir() {
+        std::fs::remove_dir_all(input_path).context(format!(
+            "Failed to remove input directory: {:?}",
+            input_path
+        ))
+    } else {
+        std::fs::remove_file(input_path)
+            .context(format!("Failed to remove input file: {:?}", input_path))
+    }
+}
+
+// Reads a whole part set and checks every part against its manifest.
+pub fn verify_parts<F>(first_part: &Path, mut progress_callback: F) -> Result<()>
+where
+    F: FnMut(ProgressInfo),
+{
+    let manifest =
+        Manifest::find_for_part(first_part)?.context("The parts have no manifest to check")?;
+    let part_files = resolve_parts(first_part, Some(&manifest))?;
+    let total_size = joined_size(&part_files, Some(&manifest));
+    let total_parts = part_files.len();
+
+    let mut verify_progress = |info: ProgressInfo| {
+        progress_callback(ProgressInfo {
+            message: format!("Checking part {}/{}", info.current_part, total_parts),
+            ..info
+        })
+    };
+    let mut reader = JoinReader::new(
+        &part_files,
+        Some(&manifest),
+        total_size,
+        &mut verify_progress,
+    );
+    io::copy(&mut reader, &mut io::sink()).context("Failed to check part files")?;
+    Ok(())
+}
+
+// Deletes a part set that has been joined: its parts, its manifest, or the
+// segments of a split ZIP. Parts in a chunk store or borrowed by a delta
+// split from another set are left alone, as other part sets still need them.
+pub fn remove_part_set(first_part: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+
+    if zip_split::is_split_zip(first_part) {
+        for segment in zip_split::find_segments(first_part)? {
+            std::fs::remove_file(&segment)
+                .context(format!("Failed to remove part file: {:?}", segment))?;
+            removed.push(segment);
+        }
+        return Ok(removed);
+    }
+
+    let manifest = Manifest::find_for_part(first_part)?;
+    let part_files = resolve_parts(first_part, manifest.as_ref())?;
+    let manifest_path = match &manifest {
+        Some(_) => Some(manifest_path_for_part(first_part)?),
+        None => None,
+    };
+
+    for (index, part) in part_files.iter().enumerate() {
+        let owned = match (&manifest, &manifest_path) {
+            (Some(manifest), Some(path)) => manifest.owns_part(path, index),
+            _ => true,
+        };
+        if owned {
+            std::fs::remove_file(part)
+                .context(format!("Failed to remove part file: {:?}", part))?;
+            removed.push(part.clone());
+        }
+    }
+
+    if let Some(manifest_path) = manifest_path {
+        std::fs::remove_file(&manifest_path)
+            .context(format!("Failed to remove manifest: {:?}", manifest_path))?;
+        removed.push(manifest_path);
+    }
+
+    Ok(removed)
+}
+
+// Where a split names its output before any conflict policy applies: after
+// the input itself, `<dir>.tar` for a directory, or `<name>.zip`.
+fn split_base_path(input_path: &Path, options: &SplitOptions) -> Result<PathBuf> {
+    if options.zip {
+        zip_split::zip_path(input_path)
+    } else if input_path.is_dir() {
+        directory::archive_path(input_path)
+    } else {
+        Ok(input_path.to_path_buf())
+    }
+}
+
+// Existing files a split of `input_path` would write over. Chunk stores
+// never conflict: every version gets a manifest of its own.
+pub fn split_conflicts(input_path: &Path, options: &SplitOptions) -> Result<Vec<PathBuf>> {
+    if options.boundary == Boundary::Cdc {
+        return Ok(Vec::new());
+    }
+    conflict::existing_split_files(&split_base_path(input_path, options)?, options.zip)
+}
+
+fn more_files(count: usize) -> String {
+    match count {
+        0 => String::new(),
+        1 => " and 1 more file".to_string(),
+        n => format!(" and {} more files", n),
+    }
+}
+
+// Splits a file into `<base_path>.partNNN` parts and writes their manifest.
+fn split_into_parts<F>(
+    input_path: &Path,
+    base_path: &Path,
+    options: &SplitOptions,
+    part_size: u64,
+    delta: Option<DeltaBase>,
+    mut progress_callback: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: FnMut(ProgressInfo),
+{
+    if part_size == 0 && options.boundary.uses_part_size() {
         anyhow::bail!("Part size must be greater than 0");
     }
 
@@ -47,32 +468,75 @@ where
         .context("Failed to get file metadata")?
         .len();
 
-    let total_parts: usize = file_size.div_ceil(part_size) as usize;
+    // Holes in the input are not read, and stay holes in uncompressed parts.
+    let sparse = sparse::has_holes(&file);
+    let sparse_parts = sparse && options.compression.is_none();
+
+    // Only fixed byte cuts know the number of parts up front.
+    let total_parts: usize = if options.boundary.is_bytes() {
+        file_size.div_ceil(part_size) as usize
+    } else {
+        0
+    };
 
-    let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+    let mut cutter = PartCutter::new(input_path, &options.boundary, part_size)?;
+    let mut reader = BufReader::with_capacity(
+        BUFFER_SIZE,
+        SparseReader::new(file).context("Failed to open input file")?,
+    );
     let mut part_paths = Vec::new();
+    let mut part_entries = Vec::new();
     let mut part_number: u32 = 1;
     let mut total_written = 0u64;
 
     while total_written < file_size {
-        let part_path = get_part_path(input_path, part_number);
-        let part_file = File::create(&part_path)
-            .context(format!("Failed to create part file: {:?}", part_path))?;
+        let cut = cutter.next_part(total_written)?;
+        let part_length = cut.end - total_written;
+
+        if let Some(delta) = &delta {
+            if let Some(entry) = delta.reuse(&mut reader, total_written, part_length)? {
+                total_written += part_length;
+                progress_callback(ProgressInfo {
+                    current_bytes: total_written,
+                    total_bytes: file_size,
+                    current_part: part_number as usize,
+                    total_parts,
+                    message: format!("Part {} unchanged", part_number),
+                });
+
+                part_entries.push(entry);
+                part_number += 1;
+                continue;
+            }
+        }
+
+        let part_path = get_part_path(base_path, part_number);
+        let part_file = AtomicFile::create(&part_path, options.durability)
+            .context(format!("Failed to create part file: {:?}", part_path))?
+            .sparse(sparse_parts);
 
-        let mut writer = BufWriter::with_capacity(BUFFER_SIZE, part_file);
+        let mut writer = PartEncoder::new(
+            BufWriter::with_capacity(BUFFER_SIZE, part_file),
+            options.compression,
+        )?;
+        let mut hasher = Sha256::new();
         let mut bytes_written_in_part = 0u64;
         let mut buffer = vec![0u8; BUFFER_SIZE];
 
+        writer
+            .write_all(&cut.prefix)
+            .context("Failed to write to part file")?;
+
         progress_callback(ProgressInfo {
             current_bytes: total_written,
             total_bytes: file_size,
             current_part: part_number as usize,
             total_parts,
-            message: format!("Splitting part {}/{}", part_number, total_parts),
+            message: split_message(part_number, total_parts),
         });
 
-        while bytes_written_in_part < part_size && total_written < file_size {
-            let remaining_in_part = part_size - bytes_written_in_part;
+        while bytes_written_in_part < part_length && total_written < file_size {
+            let remaining_in_part = part_length - bytes_written_in_part;
             let to_read = (BUFFER_SIZE as u64).min(remaining_in_part) as usize;
 
             let bytes_read = reader
@@ -82,10 +546,12 @@ where
             if bytes_read == 0 {
                 break;
             }
+            options.rate_limit.take(bytes_read as u64);
 
             writer
                 .write_all(&buffer[..bytes_read])
                 .context("Failed to write to part file")?;
+            hasher.update(&buffer[..bytes_read]);
 
             bytes_written_in_part += bytes_read as u64;
             total_written += bytes_read as u64;
@@ -95,30 +561,73 @@ where
                 total_bytes: file_size,
                 current_part: part_number as usize,
                 total_parts,
-                message: format!("Splitting part {}/{}", part_number, total_parts),
+                message: split_message(part_number, total_parts),
             });
         }
 
-        writer.flush().context("Failed to flush part file")?;
+        writer
+            .write_all(&cut.suffix)
+            .context("Failed to write to part file")?;
 
+        writer
+            .finish()
+            .context("Failed to flush part file")
+            .and_then(atomic::commit_buffered)
+            .context(format!("Failed to write part file: {:?}", part_path))?;
+
+        part_entries.push(PartEntry {
+            name: part_file_name(&part_path),
+            offset: total_written - bytes_written_in_part,
+            length: bytes_written_in_part,
+            prefix_len: cut.prefix.len() as u64,
+            suffix_len: cut.suffix.len() as u64,
+            sha256: Some(hex_digest(&hasher.finalize())),
+        });
         part_paths.push(part_path);
         part_number += 1;
     }
 
+    Manifest {
+        version: MANIFEST_VERSION,
+        file_name: part_file_name(input_path),
+        file_size,
+        part_size,
+        boundary: options.boundary.clone(),
+        compression: options.compression,
+        directory: false,
+        metadata: Some(FileMetadata::capture(input_path)?),
+        sparse,
+        parts: part_entries,
+    }
+    .save(&manifest_path(base_path), options.durability)?;
+
     progress_callback(ProgressInfo {
         current_bytes: file_size,
         total_bytes: file_size,
-        current_part: total_parts,
-        total_parts,
+        current_part: part_number as usize - 1,
+        total_parts: part_number as usize - 1,
         message: "Split complete!".to_string(),
     });
 
     Ok(part_paths)
 }
 
-pub fn join_files<F>(
+pub fn join_files<F>(first_part: &Path, output_path: &Path, progress_callback: F) -> Result<PathBuf>
+where
+    F: FnMut(ProgressInfo),
+{
+    join_files_with_options(
+        first_part,
+        output_path,
+        &JoinOptions::default(),
+        progress_callback,
+    )
+}
+
+pub fn join_files_with_options<F>(
     first_part: &Path,
     output_path: &Path,
+    options: &JoinOptions,
     mut progress_callback: F,
 ) -> Result<PathBuf>
 where
@@ -128,42 +637,236 @@ where
         anyhow::bail!("First part file does not exist: {:?}", first_part);
     }
 
-    let part_files = find_all_parts(first_part)?;
+    let output_path = &match join_conflict(first_part, output_path)? {
+        None => output_path.to_path_buf(),
+        Some(existing) => match options.on_conflict {
+            ConflictPolicy::Fail => anyhow::bail!("Output already exists: {:?}", existing),
+            ConflictPolicy::Skip => return Ok(output_path.to_path_buf()),
+            ConflictPolicy::Overwrite => output_path.to_path_buf(),
+            ConflictPolicy::Rename => conflict::free_path(output_path, |path| path.exists()),
+        },
+    };
+
+    if zip_split::is_split_zip(first_part) {
+        if options.mode == JoinMode::InPlace {
+            anyhow::bail!("Split ZIP archives cannot be joined in place");
+        }
+        if !options.ignore_space_check {
+            let segments = zip_split::find_segments(first_part)?;
+            space::ensure_space(output_path, joined_size(&segments, None))?;
+        }
+        let output_path = zip_split::join_split_zip(
+            first_part,
+            output_path,
+            options.durability,
+            &options.rate_limit,
+            progress_callback,
+        )?;
+        if options.remove_parts {
+            remove_part_set(first_part)?;
+        }
+        return Ok(output_path);
+    }
+
+    let manifest = Manifest::find_for_part(first_part)?;
+    let part_files = resolve_parts(first_part, manifest.as_ref())?;
 
     if part_files.is_empty() {
         anyhow::bail!("No part files found");
     }
 
-    let total_size: u64 = part_files
-        .iter()
-        .map(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0))
-        .sum();
+    let total_size = joined_size(&part_files, manifest.as_ref());
+    let total_parts = part_files.len();
 
-    let output_file = File::create(output_path).context("Failed to create output file")?;
+    let manifest_path = match &manifest {
+        Some(_) => Some(manifest_path_for_part(first_part)?),
+        None => None,
+    };
+    let mode = resolve_join_mode(
+        options.mode,
+        first_part,
+        &part_files,
+        manifest.as_ref(),
+        output_path,
+    )?;
 
-    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, output_file);
-    let mut total_bytes = 0u64;
-    let total_parts = part_files.len();
+    if mode == JoinMode::InPlace {
+        if let Some(reason) = in_place::unsupported_reason(
+            &part_files,
+            manifest.as_ref(),
+            manifest_path.as_deref(),
+            output_path,
+        ) {
+            anyhow::bail!("Cannot join in place: {}", reason);
+        }
+    }
 
-    for (index, part_path) in part_files.iter().enumerate() {
-        progress_callback(ProgressInfo {
-            current_bytes: total_bytes,
-            total_bytes: total_size,
-            current_part: index + 1,
-            total_parts,
-            message: format!("Joining part {}/{}", index + 1, total_parts),
-        });
+    if !options.ignore_space_check {
+        space::ensure_space(
+            output_path,
+            join_space_needed(mode, &part_files, manifest.as_ref()),
+        )?;
+    }
+
+    if mode == JoinMode::InPlace {
+        in_place::join_in_place(
+            &part_files,
+            manifest.as_ref(),
+            manifest_path.as_deref(),
+            output_path,
+            options.durability,
+            &options.rate_limit,
+            &mut progress_callback,
+        )?;
+    } else {
+        copy_parts(
+            &part_files,
+            manifest.as_ref(),
+            output_path,
+            total_size,
+            options,
+            &mut progress_callback,
+        )?;
+    }
 
-        let part_file =
-            File::open(part_path).context(format!("Failed to open part file: {:?}", part_path))?;
+    if !options.skip_metadata {
+        if let Some(metadata) = manifest.as_ref().and_then(|m| m.metadata.as_ref()) {
+            metadata.apply(output_path)?;
+        }
+    }
 
-        let mut reader = BufReader::with_capacity(BUFFER_SIZE, part_file);
-        let mut buffer = vec![0u8; BUFFER_SIZE];
+    // Joining in place has already consumed the parts.
+    if options.remove_parts && mode == JoinMode::Copy {
+        remove_part_set(first_part)?;
+    }
+
+    progress_callback(ProgressInfo {
+        current_bytes: total_size,
+        total_bytes: total_size,
+        current_part: total_parts,
+        total_parts,
+        message: "Join complete!".to_string(),
+    });
+
+    Ok(output_path.to_path_buf())
+}
+
+// The existing file or directory a join would write over, if any. A
+// directory set is restored into a directory inside `output_path`, so that
+// is what counts.
+pub fn join_conflict(first_part: &Path, output_path: &Path) -> Result<Option<PathBuf>> {
+    let is_directory = !zip_split::is_split_zip(first_part)
+        && Manifest::find_for_part(first_part)?.is_some_and(|m| m.directory);
+
+    let target = if is_directory {
+        output_path.join(directory::restored_root(first_part)?)
+    } else {
+        output_path.to_path_buf()
+    };
+
+    Ok(target.exists().then_some(target))
+}
+
+// Settles `JoinMode::Auto`: in place only when the output would not fit next
+// to the parts and the part set allows it.
+pub fn resolve_join_mode(
+    mode: JoinMode,
+    first_part: &Path,
+    part_files: &[PathBuf],
+    manifest: Option<&Manifest>,
+    output_path: &Path,
+) -> Result<JoinMode> {
+    if mode != JoinMode::Auto {
+        return Ok(mode);
+    }
+
+    let required = joined_space(part_files, manifest);
+    let fits = match space::available_space(output_path)? {
+        Some(available) => available >= required,
+        None => true,
+    };
+
+    let manifest_path = match manifest {
+        Some(_) => Some(manifest_path_for_part(first_part)?),
+        None => None,
+    };
+    if fits
+        || in_place::unsupported_reason(part_files, manifest, manifest_path.as_deref(), output_path)
+            .is_some()
+    {
+        Ok(JoinMode::Copy)
+    } else {
+        Ok(JoinMode::InPlace)
+    }
+}
+
+// Free space a join needs on the output's file system. Joining in place only
+// holds one part twice, until it is deleted after being appended.
+fn join_space_needed(mode: JoinMode, part_files: &[PathBuf], manifest: Option<&Manifest>) -> u64 {
+    if mode != JoinMode::InPlace {
+        return joined_space(part_files, manifest);
+    }
+
+    match manifest {
+        Some(manifest) => manifest
+            .parts
+            .iter()
+            .skip(1)
+            .map(|part| part.length)
+            .max()
+            .unwrap_or(0),
+        None => part_files
+            .iter()
+            .skip(1)
+            .map(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0))
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+// Disk space the joined file takes. A sparse original comes out as sparse as
+// its uncompressed parts, which hold the same holes.
+fn joined_space(part_files: &[PathBuf], manifest: Option<&Manifest>) -> u64 {
+    let size = joined_size(part_files, manifest);
+    match manifest {
+        Some(manifest) if manifest.sparse && manifest.compression.is_none() => part_files
+            .iter()
+            .map(|p| sparse::allocated_size(p))
+            .sum::<u64>()
+            .min(size),
+        _ => size,
+    }
+}
+
+fn copy_parts<F>(
+    part_files: &[PathBuf],
+    manifest: Option<&Manifest>,
+    output_path: &Path,
+    total_size: u64,
+    options: &JoinOptions,
+    progress_callback: &mut F,
+) -> Result<()>
+where
+    F: FnMut(ProgressInfo),
+{
+    let mut reader = JoinReader::new(part_files, manifest, total_size, progress_callback)
+        .rate_limit(options.rate_limit.clone());
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    if manifest.is_some_and(|m| m.directory) {
+        reader = directory::restore_directory(reader, output_path)?;
+
+        // The archive's end-of-archive padding is not read by the unpacker but
+        // still has to be checked against the manifest.
+        while reader.read_data(&mut buffer)? > 0 {}
+    } else {
+        let output_file = AtomicFile::create(output_path, options.durability)
+            .context("Failed to create output file")?
+            .sparse(manifest.is_some_and(|m| m.sparse));
+        let mut writer = BufWriter::with_capacity(BUFFER_SIZE, output_file);
 
         loop {
-            let bytes_read = reader
-                .read(&mut buffer)
-                .context("Failed to read from part file")?;
+            let bytes_read = reader.read_data(&mut buffer)?;
 
             if bytes_read == 0 {
                 break;
@@ -172,33 +875,250 @@ where
             writer
                 .write_all(&buffer[..bytes_read])
                 .context("Failed to write to output file")?;
+        }
 
-            total_bytes += bytes_read as u64;
+        atomic::commit_buffered(writer).context("Failed to write output file")?;
+    }
 
-            progress_callback(ProgressInfo {
-                current_bytes: total_bytes,
-                total_bytes: total_size,
-                current_part: index + 1,
-                total_parts,
-                message: format!("Joining part {}/{}", index + 1, total_parts),
-            });
+    Ok(())
+}
+
+// Reads the original data back out of a part set, one part after another,
+// dropping whatever the splitter added around it (a repeated CSV header, a tar
+// end-of-archive marker) and checking each part against the manifest.
+pub(crate) struct JoinReader<'a, F> {
+    part_files: &'a [PathBuf],
+    manifest: Option<&'a Manifest>,
+    index: usize,
+    current: Option<Take<Box<dyn Read>>>,
+    hasher: Option<Sha256>,
+    bytes_in_part: u64,
+    total_bytes: u64,
+    total_size: u64,
+    last_reported: u64,
+    rate_limit: RateLimit,
+    progress_callback: &'a mut F,
+}
+
+impl<'a, F> JoinReader<'a, F>
+where
+    F: FnMut(ProgressInfo),
+{
+    pub(crate) fn new(
+        part_files: &'a [PathBuf],
+        manifest: Option<&'a Manifest>,
+        total_size: u64,
+        progress_callback: &'a mut F,
+    ) -> Self {
+        Self {
+            part_files,
+            manifest,
+            index: 0,
+            current: None,
+            hasher: None,
+            bytes_in_part: 0,
+            total_bytes: 0,
+            total_size,
+            last_reported: 0,
+            rate_limit: RateLimit::default(),
+            progress_callback,
         }
     }
 
-    writer.flush().context("Failed to flush output file")?;
+    // How many parts have been read to the end and checked.
+    pub(crate) fn parts_read(&self) -> usize {
+        self.index
+    }
 
-    progress_callback(ProgressInfo {
-        current_bytes: total_size,
-        total_bytes: total_size,
-        current_part: total_parts,
-        total_parts,
-        message: "Join complete!".to_string(),
-    });
+    pub(crate) fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
 
-    Ok(output_path.to_path_buf())
+    pub(crate) fn read_data(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.index == self.part_files.len() {
+                return Ok(0);
+            }
+
+            let data = match self.current.take() {
+                Some(data) => data,
+                None => {
+                    self.report_progress();
+                    self.open_part()?
+                }
+            };
+            let data = self.current.insert(data);
+
+            let part_path = &self.part_files[self.index];
+            let bytes_read = data
+                .read(buf)
+                .context(format!("Failed to read from part file: {:?}", part_path))?;
+
+            if bytes_read == 0 {
+                if let Some(data) = self.current.take() {
+                    self.finish_part(data.into_inner())?;
+                }
+                self.index += 1;
+                self.bytes_in_part = 0;
+                continue;
+            }
+
+            self.rate_limit.take(bytes_read as u64);
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&buf[..bytes_read]);
+            }
+            self.bytes_in_part += bytes_read as u64;
+            self.total_bytes += bytes_read as u64;
+
+            if self.total_bytes - self.last_reported >= PROGRESS_INTERVAL {
+                self.report_progress();
+            }
+
+            return Ok(bytes_read);
+        }
+    }
+
+    fn open_part(&mut self) -> Result<Take<Box<dyn Read>>> {
+        let part_path = &self.part_files[self.index];
+        let part_file = File::open(part_path)
+            .and_then(SparseReader::new)
+            .context(format!("Failed to open part file: {:?}", part_path))?;
+
+        let mut reader = part_decoder(
+            BufReader::with_capacity(BUFFER_SIZE, part_file),
+            self.manifest.and_then(|m| m.compression),
+        )?;
+
+        // Drop bytes the splitter added in front of the original data, such as
+        // a repeated CSV header.
+        let entry = self.manifest.map(|m| &m.parts[self.index]);
+        let prefix_len = entry.map_or(0, |e| e.prefix_len);
+        let skipped = io::copy(&mut (&mut reader).take(prefix_len), &mut io::sink())
+            .context(format!("Failed to read from part file: {:?}", part_path))?;
+        if skipped != prefix_len {
+            anyhow::bail!("Part file {:?} is shorter than its header", part_path);
+        }
+
+        self.hasher = entry.and_then(|e| e.sha256.as_ref()).map(|_| Sha256::new());
+
+        // Only the original data is handed out; any trailer the splitter
+        // appended is read separately once the data runs out.
+        Ok(reader.take(entry.map_or(u64::MAX, |e| e.length)))
+    }
+
+    fn finish_part(&mut self, mut reader: Box<dyn Read>) -> Result<()> {
+        let part_path = &self.part_files[self.index];
+        let Some(entry) = self.manifest.map(|m| &m.parts[self.index]) else {
+            return Ok(());
+        };
+
+        let trailing = io::copy(&mut reader, &mut io::sink())
+            .context(format!("Failed to read from part file: {:?}", part_path))?;
+
+        if self.bytes_in_part != entry.length || trailing != entry.suffix_len {
+            anyhow::bail!(
+                "Part file {:?} holds {} bytes, expected {}",
+                part_path,
+                self.bytes_in_part + trailing,
+                entry.length + entry.suffix_len
+            );
+        }
+
+        if let (Some(expected), Some(hasher)) = (&entry.sha256, self.hasher.take()) {
+            if hex_digest(&hasher.finalize()) != *expected {
+                anyhow::bail!("Part file {:?} does not match its SHA-256 hash", part_path);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn report_progress(&mut self) {
+        let total_parts = self.part_files.len();
+        let current_part = (self.index + 1).min(total_parts);
+
+        self.last_reported = self.total_bytes;
+        (self.progress_callback)(ProgressInfo {
+            current_bytes: self.total_bytes,
+            total_bytes: self.total_size,
+            current_part,
+            total_parts,
+            message: format!("Joining part {}/{}", current_part, total_parts),
+        });
+    }
+}
+
+impl<F> Read for JoinReader<'_, F>
+where
+    F: FnMut(ProgressInfo),
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_data(buf)
+            .map_err(|e| io::Error::other(format!("{:#}", e)))
+    }
 }
 
-fn get_part_path(input_path: &Path, part_number: u32) -> PathBuf {
+fn split_message(part_number: u32, total_parts: usize) -> String {
+    if total_parts == 0 {
+        format!("Splitting part {}", part_number)
+    } else {
+        format!("Splitting part {}/{}", part_number, total_parts)
+    }
+}
+
+// Directory containing `path`, `.` for a bare file name.
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+pub(crate) fn part_file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_else(|| std::ffi::OsStr::new("file"))
+        .to_string_lossy()
+        .to_string()
+}
+
+// Parts listed in the manifest take precedence over scanning for sequential
+// names, so a missing trailing part is reported instead of silently skipped.
+pub fn resolve_parts(first_part: &Path, manifest: Option<&Manifest>) -> Result<Vec<PathBuf>> {
+    let Some(manifest) = manifest else {
+        return find_all_parts(first_part);
+    };
+
+    let parent_dir = first_part.parent().unwrap_or_else(|| Path::new("."));
+
+    manifest
+        .parts
+        .iter()
+        .map(|entry| {
+            let part_path = parent_dir.join(&entry.name);
+            if !part_path.exists() {
+                anyhow::bail!("Missing part file listed in manifest: {:?}", part_path);
+            }
+            Ok(part_path)
+        })
+        .collect()
+}
+
+pub fn joined_size(part_files: &[PathBuf], manifest: Option<&Manifest>) -> u64 {
+    match manifest {
+        Some(manifest) => manifest.file_size,
+        None => part_files
+            .iter()
+            .map(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0))
+            .sum(),
+    }
+}
+
+pub(crate) fn get_part_path(input_path: &Path, part_number: u32) -> PathBuf {
     let file_name = input_path
         .file_name()
         .unwrap_or_else(|| std::ffi::OsStr::new("file"));
@@ -259,3 +1179,56 @@ pub fn format_bytes(bytes: u64) -> String {
 
     format!("{:.2} {}", size, UNITS[unit_index])
 }
+
+// Parses a size such as `4294967296`, `512K`, `100MB`, `1.5G` or `2GiB`.
+// Units are binary (1K = 1024 bytes).
+pub fn parse_size(text: &str) -> Result<u64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => anyhow::bail!("Unknown size unit in {:?} (use K, M, G or T)", text),
+    };
+
+    if let Ok(value) = number.parse::<u64>() {
+        return value
+            .checked_mul(multiplier)
+            .context(format!("Size is too large: {:?}", text));
+    }
+
+    let value: f64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {:?}", text))?;
+    let bytes = value * multiplier as f64;
+    if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+        anyhow::bail!("Size is too large: {:?}", text);
+    }
+    Ok(bytes.round() as u64)
+}
+
+// UTC calendar date and time (year, month, day, hour, minute, second) of a
+// Unix timestamp, using Howard Hinnant's days-to-civil algorithm.
+pub(crate) fn civil_from_unix(unix_secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (unix_secs / 86_400) as i64;
+    let secs = (unix_secs % 86_400) as u32;
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
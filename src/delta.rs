@@ -0,0 +1,313 @@
+use crate::compression::Compression;
+use crate::core::parent_dir;
+use crate::manifest::{hex_digest, Manifest, PartEntry};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+// A previous split of the same file. Parts whose byte range and hash are
+// unchanged are not written again; the new manifest points at the old part
+// file instead, so only changed parts have to be transferred.
+pub(crate) struct DeltaBase {
+    manifest: Manifest,
+    // Where the old parts live, relative to the directory of the new manifest.
+    part_dir: PathBuf,
+}
+
+impl DeltaBase {
+    pub(crate) fn load(
+        manifest_path: &Path,
+        output_dir: &Path,
+        part_size: u64,
+        compression: Option<Compression>,
+    ) -> Result<Self> {
+        let manifest = Manifest::load(manifest_path)?;
+
+        if !manifest.boundary.is_bytes() || manifest.directory {
+            anyhow::bail!(
+                "{:?} does not describe fixed-size parts of a file",
+                manifest_path
+            );
+        }
+
+        if part_size != manifest.part_size {
+            anyhow::bail!(
+                "Part size {} does not match the {} bytes used by {:?}",
+                part_size,
+                manifest.part_size,
+                manifest_path
+            );
+        }
+
+        if compression != manifest.compression {
+            anyhow::bail!(
+                "Compression does not match the one used by {:?}",
+                manifest_path
+            );
+        }
+
+        if manifest.parts.iter().all(|part| part.sha256.is_none()) {
+            anyhow::bail!(
+                "{:?} has no part hashes; split the old file again to record them",
+                manifest_path
+            );
+        }
+
+        let manifest_dir = parent_dir(manifest_path);
+        let part_dir = relative_path(output_dir, manifest_dir)?;
+
+        Ok(Self { manifest, part_dir })
+    }
+
+    // If the old split has a part covering exactly `offset..offset + length`,
+    // hashes that range of `reader` and returns an entry for the old part when
+    // the content is unchanged. Otherwise `reader` is left at `offset`.
+    pub(crate) fn reuse<R: Read + Seek>(
+        &self,
+        reader: &mut R,
+        offset: u64,
+        length: u64,
+    ) -> Result<Option<PartEntry>> {
+        let Some(old) = self.manifest.parts.iter().find(|part| {
+            part.offset == offset
+                && part.length == length
+                && part.prefix_len == 0
+                && part.suffix_len == 0
+        }) else {
+            return Ok(None);
+        };
+        let Some(old_hash) = &old.sha256 else {
+            return Ok(None);
+        };
+
+        let mut hasher = Sha256::new();
+        let hashed = io::copy(&mut reader.by_ref().take(length), &mut hasher)
+            .context("Failed to read from input file")?;
+        let hash = hex_digest(&hasher.finalize());
+
+        if hashed == length && hash == *old_hash {
+            return Ok(Some(PartEntry {
+                name: join_name(&self.part_dir, &old.name),
+                sha256: Some(hash),
+                ..old.clone()
+            }));
+        }
+
+        reader
+            .seek(SeekFrom::Start(offset))
+            .context("Failed to seek in input file")?;
+        Ok(None)
+    }
+
+    // Bytes of `input_path` a split into `part_size` parts has to write, so
+    // free space can be checked before anything is. Hashes every range an
+    // old part could stand in for.
+    pub(crate) fn changed_bytes(&self, input_path: &Path, part_size: u64) -> Result<u64> {
+        let file = File::open(input_path).context("Failed to open input file")?;
+        let file_size = file
+            .metadata()
+            .context("Failed to get file metadata")?
+            .len();
+        let mut reader = BufReader::new(file);
+
+        let mut changed = 0;
+        let mut offset = 0;
+        while offset < file_size {
+            let length = part_size.min(file_size - offset);
+            reader
+                .seek(SeekFrom::Start(offset))
+                .context("Failed to seek in input file")?;
+            if self.reuse(&mut reader, offset, length)?.is_none() {
+                changed += length;
+            }
+            offset += length;
+        }
+        Ok(changed)
+    }
+}
+
+// `to` as seen from `from`, e.g. `../yesterday` (both are directories).
+fn relative_path(from: &Path, to: &Path) -> Result<PathBuf> {
+    let from = from
+        .canonicalize()
+        .context(format!("Failed to resolve directory: {:?}", from))?;
+    let to = to
+        .canonicalize()
+        .context(format!("Failed to resolve directory: {:?}", to))?;
+
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+    Ok(path)
+}
+
+// Manifest names always use `/`, whatever the platform.
+fn join_name(dir: &Path, name: &str) -> String {
+    let mut parts: Vec<String> = dir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    parts.push(name.to_string());
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conflict::ConflictPolicy;
+    use crate::core::{self, SplitOptions};
+    use std::fs;
+
+    #[test]
+    fn test_relative_path() {
+        let base = Path::new("test_delta_paths");
+        let _ = fs::remove_dir_all(base);
+        fs::create_dir_all(base.join("a/b")).unwrap();
+        fs::create_dir_all(base.join("c")).unwrap();
+
+        assert_eq!(relative_path(base, base).unwrap(), PathBuf::new());
+        assert_eq!(
+            relative_path(&base.join("a/b"), &base.join("c")).unwrap(),
+            PathBuf::from("../../c")
+        );
+        assert_eq!(join_name(Path::new(""), "x.part001"), "x.part001");
+        assert_eq!(join_name(Path::new("../c"), "x.part001"), "../c/x.part001");
+
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_delta_split_writes_only_changed_parts() {
+        let old_dir = Path::new("test_delta_old");
+        let new_dir = Path::new("test_delta_new");
+        let _ = fs::remove_dir_all(old_dir);
+        let _ = fs::remove_dir_all(new_dir);
+        fs::create_dir_all(old_dir).unwrap();
+        fs::create_dir_all(new_dir).unwrap();
+
+        let mut data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(old_dir.join("db.bin"), &data).unwrap();
+        let old_parts = core::split_file(&old_dir.join("db.bin"), 3000, |_| {}).unwrap();
+        assert_eq!(old_parts.len(), 4);
+
+        // Change the second part and grow the file by one part.
+        data[4000] ^= 0xff;
+        data.extend_from_slice(&[9u8; 3000]);
+        let input = new_dir.join("db.bin");
+        fs::write(&input, &data).unwrap();
+
+        // The free space check counts parts 2, 4 and 5.
+        let base = DeltaBase::load(&old_dir.join("db.bin.manifest"), new_dir, 3000, None).unwrap();
+        assert_eq!(base.changed_bytes(&input, 3000).unwrap(), 7000);
+
+        let options = SplitOptions {
+            against: Some(old_dir.join("db.bin.manifest")),
+            ..SplitOptions::new(0)
+        };
+        let written = core::split_file_with_options(&input, &options, |_| {}).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                new_dir.join("db.bin.part002"),
+                new_dir.join("db.bin.part004"),
+                new_dir.join("db.bin.part005")
+            ]
+        );
+
+        let manifest = Manifest::load(&new_dir.join("db.bin.manifest")).unwrap();
+        assert_eq!(manifest.parts[0].name, "../test_delta_old/db.bin.part001");
+        assert_eq!(manifest.parts[1].name, "db.bin.part002");
+        assert!(manifest.parts.iter().all(|part| part.sha256.is_some()));
+
+        // Part 4 was the short last part before and is full-size now.
+        let output = Path::new("test_delta_joined.bin");
+        core::join_files(&new_dir.join("db.bin.manifest"), output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), data);
+
+        fs::remove_dir_all(old_dir).unwrap();
+        fs::remove_dir_all(new_dir).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_delta_split_over_its_own_parts_keeps_reused_parts() {
+        let dir = Path::new("test_delta_overwrite");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+
+        let input = dir.join("db.bin");
+        let mut data: Vec<u8> = (0..12_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&input, &data).unwrap();
+        core::split_file(&input, 3000, |_| {}).unwrap();
+
+        data[4000] ^= 0xff;
+        fs::write(&input, &data).unwrap();
+        let options = SplitOptions {
+            against: Some(dir.join("db.bin.manifest")),
+            on_conflict: ConflictPolicy::Overwrite,
+            ..SplitOptions::new(0)
+        };
+        let written = core::split_file_with_options(&input, &options, |_| {}).unwrap();
+        assert_eq!(written, vec![dir.join("db.bin.part002")]);
+        for number in 1..=4 {
+            assert!(dir.join(format!("db.bin.part{:03}", number)).exists());
+        }
+
+        let output = dir.join("db.joined");
+        core::join_files(&dir.join("db.bin.manifest"), &output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), data);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_removing_a_delta_set_keeps_borrowed_parts() {
+        let dir = Path::new("test_delta_rename");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+
+        let input = dir.join("db.bin");
+        let mut data: Vec<u8> = (0..12_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&input, &data).unwrap();
+        let old_parts = core::split_file(&input, 3000, |_| {}).unwrap();
+        let old_data = data.clone();
+
+        data[4000] ^= 0xff;
+        fs::write(&input, &data).unwrap();
+        let options = SplitOptions {
+            against: Some(dir.join("db.bin.manifest")),
+            on_conflict: ConflictPolicy::Rename,
+            ..SplitOptions::new(0)
+        };
+        let report = core::split_file_with_report(&input, &options, |_| {}).unwrap();
+        let new_manifest = dir.join("db (1).bin.manifest");
+        assert_eq!(report.manifest_path.as_ref(), Some(&new_manifest));
+        assert_eq!(
+            Manifest::load(&new_manifest).unwrap().parts[0].name,
+            "db.bin.part001"
+        );
+
+        let removed = core::remove_part_set(&new_manifest).unwrap();
+        assert_eq!(
+            removed,
+            vec![dir.join("db (1).bin.part002"), new_manifest.clone()]
+        );
+        assert!(old_parts.iter().all(|part| part.exists()));
+
+        let output = dir.join("db.joined");
+        core::join_files(&dir.join("db.bin.manifest"), &output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), old_data);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use crate::core::{part_file_name, ProgressInfo, SplitOptions};
+use crate::manifest::{manifest_path, Manifest, MANIFEST_VERSION};
+use crate::part_reader::PartSetReader;
+use crate::part_writer::PartSetWriter;
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+// A directory is streamed into a tar archive (`archive_path`, normally
+// `<dir>.tar`) that is cut into parts as it is written, so no intermediate
+// archive is ever stored. The archive keeps permissions, mtimes and symlinks.
+pub fn split_directory<F>(
+    input_dir: &Path,
+    archive_path: &Path,
+    options: &SplitOptions,
+    progress_callback: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: FnMut(ProgressInfo),
+{
+    if !options.boundary.is_bytes() {
+        anyhow::bail!("Directories can only be split into fixed-size parts");
+    }
+
+    if options.part_size == 0 {
+        anyhow::bail!("Part size must be greater than 0");
+    }
+
+    let root_name = archive_root_name(input_dir)?;
+    let writer = PartSetWriter::new(
+        archive_path,
+        options.part_size,
+        options.compression,
+        options.durability,
+        tree_size(input_dir)?,
+        "Archiving into part",
+        progress_callback,
+    )
+    .rate_limit(options.rate_limit.clone());
+
+    let mut builder = tar::Builder::new(writer);
+    builder.mode(tar::HeaderMode::Complete);
+    builder.follow_symlinks(false);
+    builder
+        .append_dir_all(&root_name, input_dir)
+        .context(format!("Failed to archive directory: {:?}", input_dir))?;
+
+    let writer = builder
+        .into_inner()
+        .context("Failed to finish directory archive")?;
+    let (part_paths, parts, file_size) = writer.finish()?;
+
+    Manifest {
+        version: MANIFEST_VERSION,
+        file_name: part_file_name(archive_path),
+        file_size,
+        part_size: options.part_size,
+        boundary: options.boundary.clone(),
+        compression: options.compression,
+        directory: true,
+        // The tar entries carry the metadata of every file.
+        metadata: None,
+        sparse: false,
+        parts,
+    }
+    .save(&manifest_path(archive_path), options.durability)?;
+
+    Ok(part_paths)
+}
+
+// Unpacks the joined tar stream below `destination` and hands the reader back
+// so the caller can consume whatever the unpacker left unread.
+pub(crate) fn restore_directory<R: Read>(reader: R, destination: &Path) -> Result<R> {
+    fs::create_dir_all(destination).context(format!(
+        "Failed to create output directory: {:?}",
+        destination
+    ))?;
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.unpack(destination).context(format!(
+        "Failed to restore directory into {:?}",
+        destination
+    ))?;
+
+    Ok(archive.into_inner())
+}
+
+// Name of the directory a directory part set restores, taken from the first
+// entry of its archive.
+pub fn restored_root(first_part: &Path) -> Result<PathBuf> {
+    let mut archive = tar::Archive::new(PartSetReader::open(first_part)?);
+    let mut entries = archive
+        .entries()
+        .context("Failed to read directory archive")?;
+    let entry = entries
+        .next()
+        .context("Directory archive is empty")?
+        .context("Failed to read directory archive")?;
+    let path = entry.path().context("Failed to read directory archive")?;
+
+    path.components()
+        .next()
+        .map(|root| PathBuf::from(root.as_os_str()))
+        .context("Directory archive has no root directory")
+}
+
+// `photos/` is stored as `photos.tar` next to it.
+pub fn archive_path(input_dir: &Path) -> Result<PathBuf> {
+    let dir = if input_dir.file_name().is_some() {
+        input_dir.to_path_buf()
+    } else {
+        input_dir
+            .canonicalize()
+            .context(format!("Failed to resolve directory: {:?}", input_dir))?
+    };
+
+    let name = archive_root_name(&dir)?;
+    Ok(dir.with_file_name(format!("{}.tar", name)))
+}
+
+fn archive_root_name(input_dir: &Path) -> Result<String> {
+    let name = match input_dir.file_name() {
+        Some(name) => name.to_os_string(),
+        None => input_dir
+            .canonicalize()
+            .context(format!("Failed to resolve directory: {:?}", input_dir))?
+            .file_name()
+            .context("Cannot split the root directory")?
+            .to_os_string(),
+    };
+
+    Ok(name.to_string_lossy().to_string())
+}
+
+// Total size of the regular files below `dir`, without following symlinks.
+// Used as the expected archive size; tar headers add a little on top.
+pub fn tree_size(dir: &Path) -> Result<u64> {
+    let mut total = 0u64;
+
+    for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
+        let entry = entry.context(format!("Failed to read directory: {:?}", dir))?;
+        let metadata = entry
+            .metadata()
+            .context(format!("Failed to get metadata: {:?}", entry.path()))?;
+
+        if metadata.is_dir() {
+            total += tree_size(&entry.path())?;
+        } else if metadata.is_file() {
+            total += metadata.len();
+        }
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core;
+
+    #[test]
+    fn test_split_and_restore_directory() {
+        let root = Path::new("test_split_dir");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("nested/deeper")).unwrap();
+        fs::write(root.join("a.txt"), b"hello\n").unwrap();
+        fs::write(root.join("nested/big.bin"), vec![7u8; 50_000]).unwrap();
+        fs::write(root.join("nested/deeper/c.txt"), b"deep\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::os::unix::fs::symlink("../a.txt", root.join("nested/link")).unwrap();
+            fs::set_permissions(root.join("a.txt"), fs::Permissions::from_mode(0o750)).unwrap();
+        }
+
+        let parts =
+            core::split_file_with_options(root, &SplitOptions::new(16 * 1024), |_| {}).unwrap();
+        assert!(parts.len() > 3);
+        assert_eq!(parts[0], PathBuf::from("test_split_dir.tar.part001"));
+
+        let restored = Path::new("test_split_dir_restored");
+        let _ = fs::remove_dir_all(restored);
+        core::join_files(&parts[0], restored, |_| {}).unwrap();
+
+        let copy = restored.join("test_split_dir");
+        assert_eq!(fs::read(copy.join("a.txt")).unwrap(), b"hello\n");
+        assert_eq!(
+            fs::read(copy.join("nested/big.bin")).unwrap(),
+            vec![7u8; 50_000]
+        );
+        assert_eq!(
+            fs::read(copy.join("nested/deeper/c.txt")).unwrap(),
+            b"deep\n"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::read_link(copy.join("nested/link")).unwrap(),
+                PathBuf::from("../a.txt")
+            );
+            let mode = fs::metadata(copy.join("a.txt"))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o750);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(restored).unwrap();
+        fs::remove_file("test_split_dir.tar.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::console::Console;
+use anyhow::{Context, Result};
+use colored::Colorize;
+use filesplitter::core::format_bytes;
+use filesplitter::part_reader::PartSetReader;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub struct RangeExtractor {
+    reader: PartSetReader,
+    start: u64,
+    length: u64,
+    output_path: Option<PathBuf>,
+    console: Console,
+}
+
+impl RangeExtractor {
+    // A negative `offset` counts from the end of the original file; without a
+    // `length` the range runs to the end.
+    pub fn new<P: AsRef<Path>>(
+        first_part: P,
+        offset: i64,
+        length: Option<u64>,
+        output_path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let reader = PartSetReader::open(first_part.as_ref())?;
+        let file_size = reader.len();
+
+        let start = if offset < 0 {
+            file_size.saturating_sub(offset.unsigned_abs())
+        } else {
+            offset as u64
+        };
+
+        if start > file_size {
+            anyhow::bail!(
+                "Offset {} is past the end of the file ({} bytes)",
+                start,
+                file_size
+            );
+        }
+
+        let length = length.map_or(file_size - start, |length| length.min(file_size - start));
+
+        Ok(Self {
+            reader,
+            start,
+            length,
+            output_path,
+            console: Console::default(),
+        })
+    }
+
+    pub fn console(mut self, console: Console) -> Self {
+        self.console = console;
+        self
+    }
+
+    pub fn extract(mut self) -> Result<u64> {
+        self.reader
+            .seek(SeekFrom::Start(self.start))
+            .context("Failed to seek in part set")?;
+        let mut range = (&mut self.reader).take(self.length);
+
+        let Some(output_path) = &self.output_path else {
+            let mut stdout = io::stdout().lock();
+            return match io::copy(&mut range, &mut stdout).and_then(|n| stdout.flush().map(|_| n)) {
+                Ok(copied) => Ok(copied),
+                // The reader went away (e.g. `| head`); that is not an error.
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(0),
+                Err(e) => Err(e).context("Failed to write to stdout"),
+            };
+        };
+
+        let output_file = File::create(output_path)
+            .context(format!("Failed to create output file: {:?}", output_path))?;
+        let mut writer = BufWriter::new(output_file);
+        let copied = io::copy(&mut range, &mut writer).context("Failed to extract range")?;
+        writer.flush().context("Failed to flush output file")?;
+
+        self.console.line(format!(
+            "\n{} {} ({}) from offset {} to {:?}\n",
+            "✓ Extracted".green().bold(),
+            format_bytes(copied).cyan().bold(),
+            copied,
+            self.start,
+            output_path
+        ));
+
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filesplitter::core;
+    use std::fs;
+
+    #[test]
+    fn test_extract_range_to_file() {
+        let input = Path::new("test_extract_range.bin");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(input, &data).unwrap();
+        let parts = core::split_file(input, 3000, |_| {}).unwrap();
+
+        let output = PathBuf::from("test_extract_range.out");
+        let copied = RangeExtractor::new(&parts[0], 2900, Some(200), Some(output.clone()))
+            .unwrap()
+            .extract()
+            .unwrap();
+        assert_eq!(copied, 200);
+        assert_eq!(fs::read(&output).unwrap(), data[2900..3100]);
+
+        // The last 100 bytes, asking for more than there is.
+        RangeExtractor::new(&parts[0], -100, Some(1000), Some(output.clone()))
+            .unwrap()
+            .extract()
+            .unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data[9900..]);
+
+        assert!(RangeExtractor::new(&parts[0], 10_001, None, None).is_err());
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+        fs::remove_file("test_extract_range.bin.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+}
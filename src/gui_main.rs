@@ -1,8 +1,9 @@
-mod core;
-
 use eframe::egui;
+use filesplitter::conflict::ConflictPolicy;
+use filesplitter::core::{self, JoinOptions, SplitOptions};
+use filesplitter::rate_limit::RateLimit;
 use poll_promise::Promise;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
@@ -47,6 +48,14 @@ struct SplitState {
     operation: Option<Promise<Result<Vec<PathBuf>, String>>>,
     result: Option<Result<Vec<PathBuf>, String>>,
     start_time: Option<Instant>,
+    // Files from an earlier split, waiting for the user to decide about them.
+    conflict: Option<Vec<PathBuf>>,
+    // Ticked on the result card to enable deleting the input.
+    remove_source: bool,
+    // Reading the parts back, then deleting the input.
+    removal: Option<Promise<Result<(), String>>>,
+    removal_result: Option<Result<(), String>>,
+    speed_limit: SpeedLimit,
 }
 
 #[derive(Default)]
@@ -57,6 +66,48 @@ struct JoinState {
     operation: Option<Promise<Result<PathBuf, String>>>,
     result: Option<Result<PathBuf, String>>,
     start_time: Option<Instant>,
+    // Existing output, waiting for the user to decide about it.
+    conflict: Option<Vec<PathBuf>>,
+    // Ticked on the result card to enable deleting the parts.
+    remove_parts: bool,
+    removal_result: Option<Result<(), String>>,
+    speed_limit: SpeedLimit,
+}
+
+// Speed limit control on the progress card. The running job shares
+// `rate_limit`, so a change applies right away.
+struct SpeedLimit {
+    enabled: bool,
+    mib_per_sec: f64,
+    rate_limit: RateLimit,
+}
+
+impl Default for SpeedLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mib_per_sec: 50.0,
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+impl SpeedLimit {
+    fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let mut changed = ui.checkbox(&mut self.enabled, "Limit speed to").changed();
+            let value = egui::DragValue::new(&mut self.mib_per_sec)
+                .range(1.0..=10_000.0)
+                .speed(1.0)
+                .suffix(" MiB/s");
+            changed |= ui.add_enabled(self.enabled, value).changed();
+
+            if changed {
+                let rate = (self.mib_per_sec * 1024.0 * 1024.0) as u64;
+                self.rate_limit.set(self.enabled.then_some(rate));
+            }
+        });
+    }
 }
 
 impl FileSplitterApp {
@@ -82,6 +133,14 @@ impl FileSplitterApp {
                         }
                     }
 
+                    let button =
+                        egui::Button::new("  🗂 Choose Folder  ").min_size(egui::vec2(150.0, 40.0));
+                    if ui.add_enabled(!is_processing, button).clicked() {
+                        if let Some(path) = rfd::FileDialog::new().pick_folder() {
+                            self.split_state.input_file = Some(path);
+                        }
+                    }
+
                     ui.add_space(15.0);
 
                     if let Some(file) = &self.split_state.input_file {
@@ -96,7 +155,14 @@ impl FileSplitterApp {
                                 .strong()
                                 .size(14.0),
                             );
-                            if let Ok(metadata) = std::fs::metadata(file) {
+                            // Sizing a folder means walking it, too slow to do every frame.
+                            if file.is_dir() {
+                                ui.label(
+                                    egui::RichText::new("Folder (stored as a tar archive)")
+                                        .size(12.0)
+                                        .color(egui::Color32::DARK_GRAY),
+                                );
+                            } else if let Ok(metadata) = std::fs::metadata(file) {
                                 ui.label(
                                     egui::RichText::new(format!(
                                         "Size: {}",
@@ -210,7 +276,8 @@ impl FileSplitterApp {
         // Action Button
         let can_process = self.split_state.input_file.is_some()
             && self.split_state.part_size > 0
-            && self.split_state.operation.is_none();
+            && self.split_state.operation.is_none()
+            && self.split_state.conflict.is_none();
 
         ui.horizontal(|ui| {
             ui.add_space(ui.available_width() / 2.0 - 100.0);
@@ -219,7 +286,7 @@ impl FileSplitterApp {
                     .fill(egui::Color32::from_rgb(59, 130, 246))
                     .min_size(egui::vec2(200.0, 50.0));
             if ui.add_enabled(can_process, button).clicked() {
-                self.start_split();
+                self.request_split();
             }
         });
 
@@ -315,6 +382,9 @@ impl FileSplitterApp {
                             });
                         }
 
+                        ui.add_space(15.0);
+                        self.split_state.speed_limit.show(ui);
+
                         ui.add_space(5.0);
                     });
 
@@ -387,6 +457,11 @@ impl FileSplitterApp {
                                     }
                                 });
 
+                            if let Some(first_part) = parts.first() {
+                                ui.add_space(15.0);
+                                self.render_source_removal(ui, first_part);
+                            }
+
                             ui.add_space(20.0);
 
                             ui.horizontal(|ui| {
@@ -436,6 +511,101 @@ impl FileSplitterApp {
         }
     }
 
+    // Offers to delete the input once the split is done. The parts are read
+    // back in the background before anything is deleted.
+    fn render_source_removal(&mut self, ui: &mut egui::Ui, first_part: &Path) {
+        if let Some(removal) = &self.split_state.removal {
+            if let Some(result) = removal.ready() {
+                self.split_state.removal_result = Some(result.clone());
+                self.split_state.removal = None;
+            } else {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Checking the parts before deleting the source file...");
+                });
+                return;
+            }
+        }
+
+        match &self.split_state.removal_result {
+            Some(Ok(())) => {
+                ui.label(
+                    egui::RichText::new("🗑 The source file was deleted")
+                        .size(13.0)
+                        .color(egui::Color32::DARK_GRAY),
+                );
+            }
+            Some(Err(e)) => {
+                ui.label(
+                    egui::RichText::new(format!("The source file was kept: {}", e))
+                        .size(13.0)
+                        .color(egui::Color32::DARK_RED),
+                );
+            }
+            None => {
+                ui.horizontal(|ui| {
+                    ui.checkbox(
+                        &mut self.split_state.remove_source,
+                        "Delete the source file",
+                    );
+                    let button = egui::Button::new("🗑 Delete");
+                    if ui
+                        .add_enabled(self.split_state.remove_source, button)
+                        .clicked()
+                    {
+                        let input = self.split_state.input_file.clone().unwrap();
+                        let first_part = first_part.to_path_buf();
+                        self.split_state.removal =
+                            Some(Promise::spawn_thread("remove-source", move || {
+                                core::remove_split_source(&input, &first_part, |_| {})
+                                    .map_err(|e| e.to_string())
+                            }));
+                    }
+                });
+            }
+        }
+    }
+
+    // Offers to delete the parts once the join is done, unless joining in
+    // place already consumed them.
+    fn render_parts_removal(&mut self, ui: &mut egui::Ui) {
+        let first_part = self.join_state.first_part.clone().unwrap();
+
+        match &self.join_state.removal_result {
+            Some(Ok(())) => {
+                ui.label(
+                    egui::RichText::new("🗑 The part files were deleted")
+                        .size(13.0)
+                        .color(egui::Color32::DARK_GRAY),
+                );
+            }
+            Some(Err(e)) => {
+                ui.label(
+                    egui::RichText::new(format!("The parts were kept: {}", e))
+                        .size(13.0)
+                        .color(egui::Color32::DARK_RED),
+                );
+            }
+            None if first_part.exists() => {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.join_state.remove_parts, "Delete the part files");
+                    let button = egui::Button::new("🗑 Delete");
+                    if ui
+                        .add_enabled(self.join_state.remove_parts, button)
+                        .clicked()
+                    {
+                        self.join_state.removal_result = Some(
+                            core::remove_part_set(&first_part)
+                                .map(|_| ())
+                                .map_err(|e| e.to_string()),
+                        );
+                    }
+                });
+            }
+            None => {}
+        }
+    }
+
     fn render_join_tab(&mut self, ui: &mut egui::Ui) {
         let is_processing = self.join_state.operation.is_some();
 
@@ -549,7 +719,8 @@ impl FileSplitterApp {
         // Action Button
         let can_process = self.join_state.first_part.is_some()
             && self.join_state.output_file.is_some()
-            && self.join_state.operation.is_none();
+            && self.join_state.operation.is_none()
+            && self.join_state.conflict.is_none();
 
         ui.horizontal(|ui| {
             ui.add_space(ui.available_width() / 2.0 - 100.0);
@@ -558,7 +729,7 @@ impl FileSplitterApp {
                     .fill(egui::Color32::from_rgb(139, 92, 246))
                     .min_size(egui::vec2(200.0, 50.0));
             if ui.add_enabled(can_process, button).clicked() {
-                self.start_join();
+                self.request_join();
             }
         });
 
@@ -651,6 +822,9 @@ impl FileSplitterApp {
                             });
                         }
 
+                        ui.add_space(15.0);
+                        self.join_state.speed_limit.show(ui);
+
                         ui.add_space(5.0);
                     });
 
@@ -716,6 +890,9 @@ impl FileSplitterApp {
                                 );
                             }
 
+                            ui.add_space(15.0);
+                            self.render_parts_removal(ui);
+
                             ui.add_space(20.0);
 
                             ui.horizontal(|ui| {
@@ -765,14 +942,29 @@ impl FileSplitterApp {
         }
     }
 
-    fn start_split(&mut self) {
+    // Asks before writing over parts from an earlier split.
+    fn request_split(&mut self) {
         let input = self.split_state.input_file.clone().unwrap();
-        let size = self.split_state.part_size;
+        let options = SplitOptions::new(self.split_state.part_size);
+
+        match core::split_conflicts(&input, &options) {
+            Ok(existing) if !existing.is_empty() => self.split_state.conflict = Some(existing),
+            _ => self.start_split(ConflictPolicy::Fail),
+        }
+    }
+
+    fn start_split(&mut self, on_conflict: ConflictPolicy) {
+        let input = self.split_state.input_file.clone().unwrap();
+        let options = SplitOptions {
+            on_conflict,
+            rate_limit: self.split_state.speed_limit.rate_limit.clone(),
+            ..SplitOptions::new(self.split_state.part_size)
+        };
         let progress = self.split_state.progress.clone();
         self.split_state.start_time = Some(Instant::now());
 
         let promise = Promise::spawn_thread("split", move || {
-            core::split_file(&input, size, |info| {
+            core::split_file_with_options(&input, &options, |info| {
                 *progress.lock().unwrap() = Some(info);
             })
             .map_err(|e| e.to_string())
@@ -782,14 +974,30 @@ impl FileSplitterApp {
         self.split_state.result = None;
     }
 
-    fn start_join(&mut self) {
+    // Asks before writing over an existing output.
+    fn request_join(&mut self) {
         let first_part = self.join_state.first_part.clone().unwrap();
         let output = self.join_state.output_file.clone().unwrap();
+
+        match core::join_conflict(&first_part, &output) {
+            Ok(Some(existing)) => self.join_state.conflict = Some(vec![existing]),
+            _ => self.start_join(ConflictPolicy::Fail),
+        }
+    }
+
+    fn start_join(&mut self, on_conflict: ConflictPolicy) {
+        let first_part = self.join_state.first_part.clone().unwrap();
+        let output = self.join_state.output_file.clone().unwrap();
+        let options = JoinOptions {
+            on_conflict,
+            rate_limit: self.join_state.speed_limit.rate_limit.clone(),
+            ..JoinOptions::default()
+        };
         let progress = self.join_state.progress.clone();
         self.join_state.start_time = Some(Instant::now());
 
         let promise = Promise::spawn_thread("join", move || {
-            core::join_files(&first_part, &output, |info| {
+            core::join_files_with_options(&first_part, &output, &options, |info| {
                 *progress.lock().unwrap() = Some(info);
             })
             .map_err(|e| e.to_string())
@@ -798,11 +1006,76 @@ impl FileSplitterApp {
         self.join_state.operation = Some(promise);
         self.join_state.result = None;
     }
+
+    fn render_conflict_dialog(&mut self, ctx: &egui::Context) {
+        let (existing, what) = match (&self.split_state.conflict, &self.join_state.conflict) {
+            (Some(existing), _) => (existing.clone(), "Parts from an earlier split"),
+            (None, Some(existing)) => (existing.clone(), "The output"),
+            (None, None) => return,
+        };
+
+        let mut choice = None;
+        egui::Window::new("⚠ Files already exist")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                ui.label(
+                    egui::RichText::new(format!("{} already exists:", what))
+                        .size(14.0)
+                        .strong(),
+                );
+                ui.add_space(8.0);
+                for path in existing.iter().take(5) {
+                    ui.label(
+                        egui::RichText::new(path.display().to_string())
+                            .size(12.0)
+                            .monospace(),
+                    );
+                }
+                if existing.len() > 5 {
+                    ui.label(
+                        egui::RichText::new(format!("... and {} more", existing.len() - 5))
+                            .size(12.0)
+                            .color(egui::Color32::GRAY),
+                    );
+                }
+                ui.add_space(15.0);
+
+                ui.horizontal(|ui| {
+                    if ui.button("Overwrite").clicked() {
+                        choice = Some(Some(ConflictPolicy::Overwrite));
+                    }
+                    if ui.button("Keep both").clicked() {
+                        choice = Some(Some(ConflictPolicy::Rename));
+                    }
+                    if ui.button("Cancel").clicked() {
+                        choice = Some(None);
+                    }
+                });
+            });
+
+        let Some(choice) = choice else {
+            return;
+        };
+        if self.split_state.conflict.take().is_some() {
+            if let Some(policy) = choice {
+                self.start_split(policy);
+            }
+        } else if self.join_state.conflict.take().is_some() {
+            if let Some(policy) = choice {
+                self.start_join(policy);
+            }
+        }
+    }
 }
 
 impl eframe::App for FileSplitterApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        if self.split_state.operation.is_some() || self.join_state.operation.is_some() {
+        if self.split_state.operation.is_some()
+            || self.split_state.removal.is_some()
+            || self.join_state.operation.is_some()
+        {
             ctx.request_repaint();
         }
 
@@ -892,6 +1165,8 @@ impl eframe::App for FileSplitterApp {
                 });
             });
         });
+
+        self.render_conflict_dialog(ctx);
     }
 }
 
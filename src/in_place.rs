@@ -0,0 +1,297 @@
+use crate::atomic::sync_dir;
+use crate::core::{
+    joined_size, parent_dir, Durability, JoinReader, ProgressInfo, BUFFER_SIZE, PROGRESS_INTERVAL,
+};
+use crate::manifest::Manifest;
+use crate::rate_limit::RateLimit;
+use crate::sparse::{self, SparseReader};
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Why the part set cannot be joined by appending onto its first part, if so.
+pub(crate) fn unsupported_reason(
+    part_files: &[PathBuf],
+    manifest: Option<&Manifest>,
+    manifest_path: Option<&Path>,
+    output_path: &Path,
+) -> Option<&'static str> {
+    if let (Some(manifest), Some(manifest_path)) = (manifest, manifest_path) {
+        if manifest.compression.is_some() {
+            return Some("the parts are compressed");
+        }
+        if manifest.directory {
+            return Some("the parts hold a directory");
+        }
+        if manifest
+            .parts
+            .first()
+            .is_some_and(|part| part.prefix_len > 0)
+        {
+            return Some("the first part starts with a header");
+        }
+        // Chunk stores and delta splits refer to parts that other part sets
+        // still need.
+        if manifest.shares_parts(manifest_path) {
+            return Some("the parts are shared with another part set");
+        }
+    }
+
+    if !same_file_system(&part_files[0], output_path) {
+        return Some("the output is on a different file system than the first part");
+    }
+
+    None
+}
+
+// Appends parts 2..N onto part 1, deleting each part once its data is safely
+// on disk, then renames part 1 to the output. Needs free space for about one
+// part instead of the whole file. Every part is checked against the manifest
+// before anything is changed, so a bad part leaves the set untouched.
+pub(crate) fn join_in_place<F>(
+    part_files: &[PathBuf],
+    manifest: Option<&Manifest>,
+    manifest_path: Option<&Path>,
+    output_path: &Path,
+    durability: Durability,
+    rate_limit: &RateLimit,
+    progress_callback: &mut F,
+) -> Result<()>
+where
+    F: FnMut(ProgressInfo),
+{
+    let total_size = joined_size(part_files, manifest);
+    let total_parts = part_files.len();
+
+    if manifest.is_some() {
+        let mut verify_progress = |info: ProgressInfo| {
+            progress_callback(ProgressInfo {
+                message: format!("Checking part {}/{}", info.current_part, total_parts),
+                ..info
+            })
+        };
+        let mut reader = JoinReader::new(part_files, manifest, total_size, &mut verify_progress)
+            .rate_limit(rate_limit.clone());
+        io::copy(&mut reader, &mut io::sink()).context("Failed to check part files")?;
+    }
+
+    let first_part = &part_files[0];
+    let mut output = OpenOptions::new()
+        .write(true)
+        .open(first_part)
+        .context(format!("Failed to open part file: {:?}", first_part))?;
+
+    // Anything the splitter appended to the first part (e.g. a tar end
+    // marker) is cut off before the rest is appended.
+    let mut total_written = match manifest {
+        Some(manifest) => {
+            let length = manifest.parts[0].length;
+            output
+                .set_len(length)
+                .context(format!("Failed to truncate part file: {:?}", first_part))?;
+            length
+        }
+        None => output
+            .metadata()
+            .context(format!("Failed to get metadata: {:?}", first_part))?
+            .len(),
+    };
+    output
+        .seek(SeekFrom::End(0))
+        .context(format!("Failed to seek in part file: {:?}", first_part))?;
+
+    let sparse = manifest.is_some_and(|m| m.sparse);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut last_reported = 0;
+
+    for (index, part_path) in part_files.iter().enumerate().skip(1) {
+        let entry = manifest.map(|m| &m.parts[index]);
+        let part_file = File::open(part_path)
+            .and_then(SparseReader::new)
+            .context(format!("Failed to open part file: {:?}", part_path))?;
+        let mut reader = BufReader::with_capacity(BUFFER_SIZE, part_file);
+
+        let prefix_len = entry.map_or(0, |e| e.prefix_len);
+        io::copy(&mut (&mut reader).take(prefix_len), &mut io::sink())
+            .context(format!("Failed to read from part file: {:?}", part_path))?;
+        let mut data = reader.take(entry.map_or(u64::MAX, |e| e.length));
+
+        loop {
+            let bytes_read = data
+                .read(&mut buffer)
+                .context(format!("Failed to read from part file: {:?}", part_path))?;
+            if bytes_read == 0 {
+                break;
+            }
+            rate_limit.take(bytes_read as u64);
+
+            if sparse {
+                sparse::write_sparse(&mut output, &buffer[..bytes_read])
+            } else {
+                output.write_all(&buffer[..bytes_read])
+            }
+            .context(format!("Failed to write to part file: {:?}", first_part))?;
+            total_written += bytes_read as u64;
+
+            if total_written - last_reported >= PROGRESS_INTERVAL {
+                last_reported = total_written;
+                progress_callback(ProgressInfo {
+                    current_bytes: total_written,
+                    total_bytes: total_size,
+                    current_part: index + 1,
+                    total_parts,
+                    message: format!("Appending part {}/{}", index + 1, total_parts),
+                });
+            }
+        }
+
+        // The part may only go once its data can no longer be lost, whatever the
+        // durability setting. That includes zeros it ended in, which are only
+        // a length until then.
+        if sparse {
+            sparse::finish_sparse(&mut output)
+                .context(format!("Failed to set file length: {:?}", first_part))?;
+        }
+        output
+            .sync_data()
+            .context(format!("Failed to sync part file: {:?}", first_part))?;
+        fs::remove_file(part_path)
+            .context(format!("Failed to remove part file: {:?}", part_path))?;
+    }
+
+    drop(output);
+    fs::rename(first_part, output_path).context(format!(
+        "Failed to rename {:?} to {:?}",
+        first_part, output_path
+    ))?;
+    if durability == Durability::Directories {
+        sync_dir(parent_dir(output_path))?;
+    }
+
+    // The manifest describes parts that no longer exist.
+    if let Some(manifest_path) = manifest_path.filter(|path| path.exists()) {
+        fs::remove_file(manifest_path)
+            .context(format!("Failed to remove manifest: {:?}", manifest_path))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn same_file_system(part: &Path, output_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::metadata(part), fs::metadata(parent_dir(output_path))) {
+        (Ok(part), Ok(dir)) => part.dev() == dir.dev(),
+        _ => false,
+    }
+}
+
+// Without device numbers, a rename failure is reported when it happens.
+#[cfg(not(unix))]
+fn same_file_system(_part: &Path, _output_path: &Path) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::Boundary;
+    use crate::conflict::ConflictPolicy;
+    use crate::core::{self, JoinMode, JoinOptions, SplitOptions};
+
+    #[test]
+    fn test_join_in_place_consumes_parts() {
+        let input = Path::new("test_in_place.csv");
+        let mut data = b"id,value\n".to_vec();
+        for i in 0..3000 {
+            data.extend_from_slice(format!("{},{}\n", i, i * 7).as_bytes());
+        }
+        fs::write(input, &data).unwrap();
+
+        // CSV parts repeat the header, which must not end up in the output.
+        let options = SplitOptions {
+            boundary: Boundary::Csv,
+            ..SplitOptions::new(5000)
+        };
+        let parts = core::split_file_with_options(input, &options, |_| {}).unwrap();
+        assert!(parts.len() > 3);
+
+        let output = Path::new("test_in_place_joined.csv");
+        let join_options = JoinOptions {
+            mode: JoinMode::InPlace,
+            ..JoinOptions::default()
+        };
+        core::join_files_with_options(&parts[0], output, &join_options, |_| {}).unwrap();
+
+        assert_eq!(fs::read(output).unwrap(), data);
+        assert!(parts.iter().all(|part| !part.exists()));
+        assert!(!Path::new("test_in_place.csv.manifest").exists());
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_bad_part_leaves_set_untouched() {
+        let input = Path::new("test_in_place_bad.bin");
+        fs::write(input, vec![5u8; 10_000]).unwrap();
+        let parts = core::split_file(input, 3000, |_| {}).unwrap();
+        fs::write(&parts[2], vec![6u8; 3000]).unwrap();
+
+        let output = Path::new("test_in_place_bad.out");
+        let join_options = JoinOptions {
+            mode: JoinMode::InPlace,
+            ..JoinOptions::default()
+        };
+        assert!(core::join_files_with_options(&parts[0], output, &join_options, |_| {}).is_err());
+        assert!(parts.iter().all(|part| part.exists()));
+        assert_eq!(fs::metadata(&parts[0]).unwrap().len(), 3000);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_in_place_bad.bin.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_delta_set_borrowing_parts_is_not_joined_in_place() {
+        let dir = Path::new("test_in_place_delta");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+
+        let input = dir.join("db.bin");
+        let mut data = vec![3u8; 9000];
+        fs::write(&input, &data).unwrap();
+        let old_parts = core::split_file(&input, 3000, |_| {}).unwrap();
+
+        // The new set is written under another name next to the old one and
+        // borrows its unchanged parts.
+        data[100] = 4;
+        fs::write(&input, &data).unwrap();
+        let options = SplitOptions {
+            against: Some(dir.join("db.bin.manifest")),
+            on_conflict: ConflictPolicy::Rename,
+            ..SplitOptions::new(0)
+        };
+        core::split_file_with_options(&input, &options, |_| {}).unwrap();
+
+        let join_options = JoinOptions {
+            mode: JoinMode::InPlace,
+            ..JoinOptions::default()
+        };
+        let error = core::join_files_with_options(
+            &dir.join("db (1).bin.manifest"),
+            &dir.join("db.out"),
+            &join_options,
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("shared with another part set"));
+        assert!(old_parts.iter().all(|part| part.exists()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,19 +1,45 @@
-use anyhow::{Context, Result};
-use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
+use crate::console::Console;
+use crate::report::{self, PartReport};
+use crate::splitter::conflict_action;
+use anyhow::Result;
+use colored::{Color, Colorize};
+use filesplitter::conflict::ConflictPolicy;
+use filesplitter::core::{self, format_bytes, JoinMode, JoinOptions};
+use filesplitter::manifest::Manifest;
+use filesplitter::zip_split;
+use serde::Serialize;
 use std::path::{Path, PathBuf};
-
-const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8MB buffer for fast I/O
+use std::time::Instant;
 
 pub struct FileJoiner {
+    first_part: PathBuf,
     part_files: Vec<PathBuf>,
+    manifest: Option<Manifest>,
     output_path: PathBuf,
+    options: JoinOptions,
+    console: Console,
+}
+
+// The result of `join --output json`.
+#[derive(Serialize)]
+struct JoinReport {
+    output: PathBuf,
+    size: u64,
+    mode: JoinMode,
+    // The output already existed and was kept under `--on-conflict skip`.
+    skipped: bool,
+    // The parts as they were before the join, which may have consumed them.
+    parts: Vec<PartReport>,
+    parts_removed: bool,
+    elapsed_secs: f64,
 }
 
 impl FileJoiner {
-    pub fn new<P: AsRef<Path>>(first_part: P, output_path: P) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(
+        first_part: P,
+        output_path: P,
+        options: JoinOptions,
+    ) -> Result<Self> {
         let first_part = first_part.as_ref().to_path_buf();
         let output_path = output_path.as_ref().to_path_buf();
 
@@ -21,171 +47,188 @@ impl FileJoiner {
             anyhow::bail!("First part file does not exist: {:?}", first_part);
         }
 
-        let part_files = Self::find_all_parts(&first_part)?;
+        let (manifest, part_files) = if zip_split::is_split_zip(&first_part) {
+            (None, zip_split::find_segments(&first_part)?)
+        } else {
+            let manifest = Manifest::find_for_part(&first_part)?;
+            let part_files = core::resolve_parts(&first_part, manifest.as_ref())?;
+            (manifest, part_files)
+        };
 
         if part_files.is_empty() {
             anyhow::bail!("No part files found");
         }
 
+        // Settle `auto` up front so the banner shows what will happen.
+        let mut options = options;
+        options.mode = if zip_split::is_split_zip(&first_part) {
+            JoinMode::Copy
+        } else {
+            core::resolve_join_mode(
+                options.mode,
+                &first_part,
+                &part_files,
+                manifest.as_ref(),
+                &output_path,
+            )?
+        };
+
+        // Joining in place consumes the parts, so it is never picked for a
+        // plain copy that does not fit; it is only suggested.
+        if options.mode == JoinMode::Copy
+            && !options.ignore_space_check
+            && !zip_split::is_split_zip(&first_part)
+            && core::resolve_join_mode(
+                JoinMode::Auto,
+                &first_part,
+                &part_files,
+                manifest.as_ref(),
+                &output_path,
+            )? == JoinMode::InPlace
+        {
+            anyhow::bail!(
+                "Not enough free space for a copy of {} next to the parts; \
+                 pass --mode in-place to append them onto the first part instead, \
+                 deleting each one as it goes",
+                format_bytes(core::joined_size(&part_files, manifest.as_ref()))
+            );
+        }
+
         Ok(Self {
+            first_part,
             part_files,
+            manifest,
             output_path,
+            options,
+            console: Console::default(),
         })
     }
 
+    pub fn console(mut self, console: Console) -> Self {
+        self.console = console;
+        self
+    }
+
     pub fn join(&self) -> Result<PathBuf> {
-        let total_size: u64 = self
-            .part_files
-            .iter()
-            .map(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0))
-            .sum();
-
-        println!(
-            "\n{}",
-            "═══════════════════════════════════════".bright_magenta()
-        );
-        println!("{}", "           FILE JOINER".bright_magenta().bold());
-        println!(
-            "{}",
-            "═══════════════════════════════════════".bright_magenta()
-        );
+        if self.console.is_json() {
+            return self.join_json();
+        }
 
-        println!(
-            "\n{} {}",
-            "Number of parts:".green().bold(),
-            self.part_files.len().to_string().cyan()
-        );
-        println!(
-            "{} {}",
-            "Total size:".green().bold(),
-            format_bytes(total_size).yellow()
-        );
-        println!("{} {:?}\n", "Output file:".green().bold(), self.output_path);
-
-        let pb = ProgressBar::new(total_size);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:50.magenta/blue}] {bytes}/{total_bytes} ({percent}%) {msg} ETA: {eta}")
-                .unwrap()
-                .progress_chars("█▓▒░ "),
-        );
+        let start = Instant::now();
+        let console = &self.console;
+        let total_size = core::joined_size(&self.part_files, self.manifest.as_ref());
 
-        let output_file =
-            File::create(&self.output_path).context("Failed to create output file")?;
+        console.title("           FILE JOINER", Color::BrightMagenta);
+        console.blank();
 
-        let mut writer = BufWriter::with_capacity(BUFFER_SIZE, output_file);
-        let mut total_bytes = 0u64;
+        console.field("Number of parts:", self.part_files.len().to_string().cyan());
+        console.field("Total size:", format_bytes(total_size).yellow());
+        if let Some(compression) = self.manifest.as_ref().and_then(|m| m.compression) {
+            console.field("Compression:", format!("{:?}", compression).yellow());
+        }
+        if self.options.mode == JoinMode::InPlace {
+            console.field("Mode:", "in place (parts are consumed)".yellow());
+        }
+        if let Some(rate) = self.options.rate_limit.get() {
+            console.field("Rate limit:", format!("{}/s", format_bytes(rate)).yellow());
+        }
+        console.field(self.output_label(), format!("{:?}", self.output_path));
+        let existing = core::join_conflict(&self.first_part, &self.output_path)?;
+        if let Some(existing) = &existing {
+            console.field(
+                "Already exists:",
+                format!(
+                    "{:?} ({})",
+                    existing,
+                    conflict_action(self.options.on_conflict).yellow()
+                ),
+            );
+        }
+        console.blank();
 
-        for (index, part_path) in self.part_files.iter().enumerate() {
-            pb.set_message(format!(
-                "Joining part {}/{}",
-                index + 1,
-                self.part_files.len()
+        if existing.is_some() && self.options.on_conflict == ConflictPolicy::Skip {
+            console.line(format!(
+                "{}\n",
+                "✓ Skipped: the existing output is kept".green().bold()
             ));
-
-            let part_file = File::open(part_path)
-                .context(format!("Failed to open part file: {:?}", part_path))?;
-
-            let mut reader = BufReader::with_capacity(BUFFER_SIZE, part_file);
-            let mut buffer = vec![0u8; BUFFER_SIZE];
-
-            loop {
-                let bytes_read = reader
-                    .read(&mut buffer)
-                    .context("Failed to read from part file")?;
-
-                if bytes_read == 0 {
-                    break;
-                }
-
-                writer
-                    .write_all(&buffer[..bytes_read])
-                    .context("Failed to write to output file")?;
-
-                total_bytes += bytes_read as u64;
-                pb.set_position(total_bytes);
-            }
+            return Ok(self.output_path.clone());
         }
 
-        writer.flush().context("Failed to flush output file")?;
+        let progress = console.progress(total_size, "magenta");
 
-        pb.finish_with_message("Join complete!".green().to_string());
+        let output_path = core::join_files_with_options(
+            &self.first_part,
+            &self.output_path,
+            &self.options,
+            |info| progress.update(info),
+        )?;
 
-        println!(
-            "\n{}",
-            "═══════════════════════════════════════".bright_magenta()
-        );
-        println!(
-            "{} {}",
-            "✓ Successfully joined".green().bold(),
-            format_bytes(total_bytes).cyan().bold()
-        );
-        println!(
-            "{}",
-            "═══════════════════════════════════════".bright_magenta()
+        progress.finish("Join complete!");
+
+        console.success(
+            "✓ Successfully joined",
+            format_bytes(total_size).cyan().bold(),
+            Color::BrightMagenta,
         );
 
-        println!(
+        console.line(format!(
             "\n{} {:?}\n",
-            "Output file:".yellow().bold(),
-            self.output_path
-        );
+            self.output_label().yellow().bold(),
+            output_path
+        ));
+        if self.options.remove_parts && self.options.mode == JoinMode::Copy {
+            console.line(format!("{}\n", "✓ Removed the part files".green().bold()));
+        }
+        console.elapsed(start.elapsed());
 
-        Ok(self.output_path.clone())
+        Ok(output_path)
     }
 
-    fn find_all_parts(first_part: &Path) -> Result<Vec<PathBuf>> {
-        let file_name = first_part
-            .file_name()
-            .context("Invalid file name")?
-            .to_string_lossy();
+    fn join_json(&self) -> Result<PathBuf> {
+        let start = Instant::now();
+        let parts = report::part_reports(&self.part_files, self.manifest.as_ref());
+        let size = core::joined_size(&self.part_files, self.manifest.as_ref());
+        let progress = self.console.progress(size, "magenta");
 
-        // Extract base name (remove .partXXX)
-        let base_name = if let Some(pos) = file_name.rfind(".part") {
-            &file_name[..pos]
+        let skipped = self.options.on_conflict == ConflictPolicy::Skip
+            && core::join_conflict(&self.first_part, &self.output_path)?.is_some();
+        let output_path = if skipped {
+            self.output_path.clone()
         } else {
-            anyhow::bail!("First part file must have .partXXX extension");
+            core::join_files_with_options(
+                &self.first_part,
+                &self.output_path,
+                &self.options,
+                |info| progress.update(info),
+            )?
         };
 
-        let parent_dir = first_part.parent().unwrap_or_else(|| Path::new("."));
-
-        let mut parts = Vec::new();
-        let mut part_number = 1;
-
-        loop {
-            let part_name = format!("{}.part{:03}", base_name, part_number);
-            let part_path = parent_dir.join(&part_name);
-
-            if !part_path.exists() {
-                break;
-            }
-
-            parts.push(part_path);
-            part_number += 1;
-        }
-
-        if parts.is_empty() {
-            anyhow::bail!(
-                "No sequential part files found starting from: {:?}",
-                first_part
-            );
-        }
+        report::emit(
+            "result",
+            JoinReport {
+                output: output_path.clone(),
+                size,
+                mode: self.options.mode,
+                skipped,
+                parts,
+                parts_removed: !skipped
+                    && self.options.remove_parts
+                    && self.options.mode == JoinMode::Copy,
+                elapsed_secs: start.elapsed().as_secs_f64(),
+            },
+        );
 
-        Ok(parts)
+        Ok(output_path)
     }
-}
-
-fn format_bytes(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
-    let mut size = bytes as f64;
-    let mut unit_index = 0;
 
-    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+    // Part sets made from a directory are restored into the output directory.
+    fn output_label(&self) -> &'static str {
+        if self.manifest.as_ref().is_some_and(|m| m.directory) {
+            "Output directory:"
+        } else {
+            "Output file:"
+        }
     }
-
-    format!("{:.2} {}", size, UNITS[unit_index])
 }
 
 #[cfg(test)]
@@ -203,7 +246,7 @@ mod tests {
 
     #[test]
     fn test_new_validates_first_part_exists() {
-        let result = FileJoiner::new("nonexistent.part001", "output.bin");
+        let result = FileJoiner::new("nonexistent.part001", "output.bin", JoinOptions::default());
         assert!(result.is_err());
     }
 
@@ -222,7 +265,8 @@ mod tests {
             .write_all(b"part3")
             .unwrap();
 
-        let joiner = FileJoiner::new("test.bin.part001", "output.bin").unwrap();
+        let joiner =
+            FileJoiner::new("test.bin.part001", "output.bin", JoinOptions::default()).unwrap();
         assert_eq!(joiner.part_files.len(), 3);
 
         fs::remove_file("test.bin.part001").unwrap();
@@ -243,7 +287,12 @@ mod tests {
             .write_all(&original_data[20..])
             .unwrap();
 
-        let joiner = FileJoiner::new("join_test.dat.part001", "join_test_output.dat").unwrap();
+        let joiner = FileJoiner::new(
+            "join_test.dat.part001",
+            "join_test_output.dat",
+            JoinOptions::default(),
+        )
+        .unwrap();
         joiner.join().unwrap();
 
         let mut restored = Vec::new();
@@ -258,4 +307,133 @@ mod tests {
         fs::remove_file("join_test.dat.part002").unwrap();
         fs::remove_file("join_test_output.dat").unwrap();
     }
+
+    #[test]
+    fn test_join_compressed_parts() {
+        use filesplitter::compression::Compression;
+        use filesplitter::core::SplitOptions;
+
+        let original_data = b"2024-01-01 12:00:00 INFO request served\n".repeat(500);
+        fs::File::create("join_zstd.log")
+            .unwrap()
+            .write_all(&original_data)
+            .unwrap();
+
+        let options = SplitOptions {
+            compression: Some(Compression::Zstd),
+            ..SplitOptions::new(4096)
+        };
+        let parts =
+            core::split_file_with_options(Path::new("join_zstd.log"), &options, |_| {}).unwrap();
+        assert_eq!(parts.len(), original_data.len().div_ceil(4096));
+
+        // Each part is a complete frame that decompresses on its own.
+        let mut third = Vec::new();
+        zstd::Decoder::new(fs::File::open(&parts[2]).unwrap())
+            .unwrap()
+            .read_to_end(&mut third)
+            .unwrap();
+        assert_eq!(third, &original_data[8192..12288]);
+
+        let joiner = FileJoiner::new(
+            "join_zstd.log.part001",
+            "join_zstd_output.log",
+            JoinOptions::default(),
+        )
+        .unwrap();
+        joiner.join().unwrap();
+        assert_eq!(fs::read("join_zstd_output.log").unwrap(), original_data);
+
+        fs::remove_file("join_zstd.log").unwrap();
+        fs::remove_file("join_zstd.log.manifest").unwrap();
+        fs::remove_file("join_zstd_output.log").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_join_csv_parts_strips_repeated_headers() {
+        use filesplitter::boundary::Boundary;
+        use filesplitter::core::SplitOptions;
+
+        let mut original_data = b"id,name,comment\n".to_vec();
+        for i in 0..200 {
+            original_data
+                .extend_from_slice(format!("{},user{},\"line one\nline two\"\n", i, i).as_bytes());
+        }
+        fs::write("join_test.csv", &original_data).unwrap();
+
+        let options = SplitOptions {
+            boundary: Boundary::Csv,
+            ..SplitOptions::new(1000)
+        };
+        let parts =
+            core::split_file_with_options(Path::new("join_test.csv"), &options, |_| {}).unwrap();
+        assert!(parts.len() > 1);
+
+        for part in &parts {
+            let data = fs::read(part).unwrap();
+            assert!(data.len() <= 1000);
+            assert!(data.starts_with(b"id,name,comment\n"));
+            assert!(data.ends_with(b"line two\"\n"));
+        }
+
+        let joiner = FileJoiner::new(
+            "join_test.csv.part001",
+            "join_test_output.csv",
+            JoinOptions::default(),
+        )
+        .unwrap();
+        joiner.join().unwrap();
+        assert_eq!(fs::read("join_test_output.csv").unwrap(), original_data);
+
+        fs::remove_file("join_test.csv").unwrap();
+        fs::remove_file("join_test.csv.manifest").unwrap();
+        fs::remove_file("join_test_output.csv").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remove_source_and_parts() {
+        use filesplitter::core::SplitOptions;
+
+        let original_data: Vec<u8> = (0..9000u32).map(|i| (i % 253) as u8).collect();
+        fs::write("remove_test.bin", &original_data).unwrap();
+
+        // A part that no longer matches the manifest keeps the source.
+        let parts = core::split_file(Path::new("remove_test.bin"), 4000, |_| {}).unwrap();
+        fs::write(&parts[1], vec![0u8; 4000]).unwrap();
+        assert!(core::remove_split_source(
+            Path::new("remove_test.bin"),
+            Path::new("remove_test.bin.manifest"),
+            |_| {}
+        )
+        .is_err());
+        assert!(Path::new("remove_test.bin").exists());
+
+        let options = SplitOptions {
+            on_conflict: ConflictPolicy::Overwrite,
+            remove_source: true,
+            ..SplitOptions::new(4000)
+        };
+        core::split_file_with_options(Path::new("remove_test.bin"), &options, |_| {}).unwrap();
+        assert!(!Path::new("remove_test.bin").exists());
+
+        let options = JoinOptions {
+            mode: JoinMode::Copy,
+            remove_parts: true,
+            ..JoinOptions::default()
+        };
+        let joiner =
+            FileJoiner::new("remove_test.bin.part001", "remove_test.out", options).unwrap();
+        joiner.join().unwrap();
+        assert_eq!(fs::read("remove_test.out").unwrap(), original_data);
+        assert!(parts.iter().all(|part| !part.exists()));
+        assert!(!Path::new("remove_test.bin.manifest").exists());
+
+        fs::remove_file("remove_test.out").unwrap();
+    }
 }
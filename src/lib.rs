@@ -0,0 +1,22 @@
+mod atomic;
+pub mod boundary;
+pub mod cdc;
+pub mod compression;
+pub mod conflict;
+pub mod core;
+mod delta;
+pub mod directory;
+mod in_place;
+pub mod manifest;
+pub mod metadata;
+#[cfg(target_os = "linux")]
+pub mod mount;
+pub mod ndjson;
+pub mod part_reader;
+mod part_writer;
+pub mod rate_limit;
+pub mod rechunk;
+pub mod space;
+mod sparse;
+mod tar_split;
+pub mod zip_split;
@@ -1,9 +1,21 @@
+mod console;
+mod extractor;
 mod joiner;
+mod rechunker;
+mod report;
 mod splitter;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
+use console::{Console, Verbosity};
+use filesplitter::boundary::{unescape_delimiter, Boundary};
+use filesplitter::compression::Compression;
+use filesplitter::conflict::ConflictPolicy;
+use filesplitter::core::{self, Durability, JoinMode, JoinOptions, SplitOptions};
+use filesplitter::rate_limit::{self, RateLimit};
+use filesplitter::rechunk::RechunkOptions;
+use report::OutputFormat;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -11,88 +23,515 @@ use std::path::PathBuf;
 #[command(about = "Fast file splitter and joiner for RAR and other files", long_about = None)]
 #[command(version)]
 struct Cli {
+    #[arg(
+        long,
+        value_enum,
+        default_value = "text",
+        help = "Output format; json prints one JSON object per line and nothing else (split and join only)"
+    )]
+    output: OutputFormat,
+
+    #[arg(long, help = "With --output json, also emit progress events")]
+    progress_events: bool,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "verbose",
+        help = "Print only errors and warnings"
+    )]
+    quiet: bool,
+
+    #[arg(
+        short,
+        long,
+        help = "Also print part sizes, timings and every progress step"
+    )]
+    verbose: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum BoundaryArg {
+    Bytes,
+    Line,
+    Csv,
+    Ndjson,
+    Tar,
+    Cdc,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     #[command(about = "Split a file into multiple parts")]
     Split {
-        #[arg(short, long, help = "Input file to split")]
+        #[arg(short, long, help = "Input file or directory to split")]
         input: PathBuf,
 
         #[arg(
             short,
             long,
-            help = "Size of each part in bytes (e.g., 4294967296 for 4GB)"
+            required_unless_present_any = ["lines", "against"],
+            value_parser = parse_size,
+            help = "Size of each part (e.g., 4294967296, 500M or 4G)"
         )]
-        size: u64,
+        size: Option<u64>,
+
+        #[arg(
+            short,
+            long,
+            value_enum,
+            default_value_t = BoundaryArg::Bytes,
+            help = "Where parts may end: exact byte offsets, or the last line / CSV record / NDJSON record / tar member within --size; cdc cuts content-defined chunks averaging --size into --store"
+        )]
+        boundary: BoundaryArg,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["size", "boundary"],
+            help = "Split into parts of this many lines each"
+        )]
+        lines: Option<u64>,
+
+        #[arg(
+            long,
+            help = "Check that every record is valid JSON and report malformed ones (with --boundary ndjson)"
+        )]
+        validate: bool,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["boundary", "lines", "regex"],
+            help = "End each part after the last occurrence of this delimiter (escapes: \\0 \\n \\r \\t \\xNN)"
+        )]
+        delimiter: Option<String>,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["boundary", "lines"],
+            help = "Start each part at a line matching this regex (e.g. '^BEGIN:VCARD')"
+        )]
+        regex: Option<String>,
+
+        #[arg(
+            short,
+            long,
+            value_enum,
+            help = "Compress each part as an independent frame (gzip or zstd)"
+        )]
+        compress: Option<Compression>,
+
+        #[arg(
+            long,
+            conflicts_with_all = ["boundary", "lines", "delimiter", "regex", "compress"],
+            help = "Write a standard split ZIP (.z01, .z02, ..., .zip) that reassembles into a normal ZIP"
+        )]
+        zip: bool,
+
+        #[arg(
+            long,
+            help = "Chunk store directory for --boundary cdc (chunks are stored once, by hash)"
+        )]
+        store: Option<PathBuf>,
+
+        #[arg(
+            long,
+            value_name = "MANIFEST",
+            conflicts_with_all = ["boundary", "lines", "delimiter", "regex", "zip"],
+            help = "Only write parts that changed since the split described by this manifest"
+        )]
+        against: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Write the parts even if the disk looks too full for them"
+        )]
+        ignore_space_check: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "files",
+            help = "What to fsync before reporting success: none, files, or files and their directories"
+        )]
+        durability: Durability,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "fail",
+            help = "When parts from an earlier split exist: fail, overwrite, skip, or rename the new ones (`name (1)`)"
+        )]
+        on_conflict: ConflictPolicy,
+
+        #[arg(
+            long,
+            help = "Delete the input once the parts are written and read back intact"
+        )]
+        remove_source: bool,
+
+        #[arg(
+            long,
+            value_parser = parse_rate,
+            help = "Cap the read rate to spare the disks (e.g., 50MiB/s)"
+        )]
+        rate_limit: Option<u64>,
     },
 
     #[command(about = "Join part files back into original file")]
     Join {
-        #[arg(short, long, help = "First part file (e.g., file.rar.part001)")]
+        #[arg(
+            short,
+            long,
+            help = "First part file (e.g., file.rar.part001, archive.z01 for a split ZIP, or a manifest in a chunk store)"
+        )]
         input: PathBuf,
 
         #[arg(short, long, help = "Output file path")]
         output: PathBuf,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "copy",
+            help = "copy keeps the parts; in-place appends them onto the first part and deletes them; auto joins in place only when there is not enough free space for a copy"
+        )]
+        mode: JoinMode,
+
+        #[arg(long, help = "Write the output even if the disk looks too full for it")]
+        ignore_space_check: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "files",
+            help = "What to fsync before reporting success: none, files, or files and their directories"
+        )]
+        durability: Durability,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "fail",
+            help = "When the output already exists: fail, overwrite, skip, or rename the new one (`name (1)`)"
+        )]
+        on_conflict: ConflictPolicy,
+
+        #[arg(
+            long,
+            help = "Don't restore the original modification time, permissions, ownership and extended attributes"
+        )]
+        no_metadata: bool,
+
+        #[arg(
+            long,
+            help = "Delete the parts and their manifest once the output is complete"
+        )]
+        remove_parts: bool,
+
+        #[arg(
+            long,
+            value_parser = parse_rate,
+            help = "Cap the read rate to spare the disks (e.g., 50MiB/s)"
+        )]
+        rate_limit: Option<u64>,
+    },
+
+    #[command(
+        visible_alias = "extract-range",
+        about = "Print a byte range of the original file straight from its parts"
+    )]
+    Cat {
+        #[arg(help = "First part file or manifest")]
+        input: PathBuf,
+
+        #[arg(
+            long,
+            default_value_t = 0,
+            allow_negative_numbers = true,
+            help = "Where the range starts; negative values count back from the end"
+        )]
+        offset: i64,
+
+        #[arg(long, help = "Number of bytes to read (default: up to the end)")]
+        length: Option<u64>,
+
+        #[arg(short, long, help = "Write the range to this file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+
+    #[command(about = "Re-cut a part set into parts of a new size without joining it")]
+    Rechunk {
+        #[arg(help = "First part file or manifest")]
+        input: PathBuf,
+
+        #[arg(
+            short,
+            long,
+            value_parser = parse_size,
+            help = "New size of each part (e.g., 2G)"
+        )]
+        size: u64,
+
+        #[arg(
+            long,
+            help = "Write the new parts here instead of next to the old ones"
+        )]
+        output_dir: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Delete each old part once its data is committed to new parts (needs far less free space)"
+        )]
+        remove_old: bool,
+
+        #[arg(
+            long,
+            value_enum,
+            default_value = "files",
+            help = "What to fsync before reporting success: none, files, or files and their directories"
+        )]
+        durability: Durability,
+    },
+
+    #[cfg(target_os = "linux")]
+    #[command(about = "Mount a part set as a single read-only file (FUSE)")]
+    Mount {
+        #[arg(help = "First part file or manifest")]
+        input: PathBuf,
+
+        #[arg(help = "Directory to mount on")]
+        mountpoint: PathBuf,
+
+        #[arg(
+            long,
+            help = "Skip checking parts against the SHA-256 hashes in the manifest"
+        )]
+        no_verify: bool,
     },
 }
 
 fn main() {
-    print_banner();
+    let cli = Cli::parse();
+
+    let verbosity = if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    };
+    let console = Console::new(cli.output, verbosity, cli.progress_events);
+
+    // `cat` without `--output` writes the data itself to stdout.
+    if !matches!(cli.command, Commands::Cat { output: None, .. }) {
+        console.banner();
+    }
 
-    if let Err(e) = run() {
-        eprintln!("\n{} {}", "✗ Error:".red().bold(), e.to_string().red());
+    if let Err(e) = run(cli, console) {
+        console.error(&e);
         std::process::exit(1);
     }
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+fn run(cli: Cli, console: Console) -> Result<()> {
+    if cli.progress_events && cli.output != OutputFormat::Json {
+        anyhow::bail!("--progress-events requires --output json");
+    }
+    if cli.output == OutputFormat::Json
+        && !matches!(cli.command, Commands::Split { .. } | Commands::Join { .. })
+    {
+        anyhow::bail!("--output json is only supported by split and join");
+    }
 
     match cli.command {
-        Commands::Split { input, size } => {
-            handle_split(input, size)?;
+        Commands::Split {
+            input,
+            size,
+            boundary,
+            lines,
+            validate,
+            delimiter,
+            regex,
+            compress,
+            zip,
+            store,
+            against,
+            ignore_space_check,
+            durability,
+            on_conflict,
+            remove_source,
+            rate_limit,
+        } => {
+            if validate && boundary != BoundaryArg::Ndjson {
+                anyhow::bail!("--validate requires --boundary ndjson");
+            }
+            if (boundary == BoundaryArg::Cdc) != store.is_some() {
+                anyhow::bail!("--boundary cdc and --store must be used together");
+            }
+            let boundary = if let Some(count) = lines {
+                Boundary::Lines { count }
+            } else if let Some(delimiter) = delimiter {
+                Boundary::Delimiter {
+                    delimiter: unescape_delimiter(&delimiter)?,
+                }
+            } else if let Some(pattern) = regex {
+                Boundary::Regex { pattern }
+            } else {
+                match boundary {
+                    BoundaryArg::Bytes => Boundary::Bytes,
+                    BoundaryArg::Line => Boundary::Line,
+                    BoundaryArg::Csv => Boundary::Csv,
+                    BoundaryArg::Ndjson => Boundary::Ndjson,
+                    BoundaryArg::Tar => Boundary::Tar,
+                    BoundaryArg::Cdc => Boundary::Cdc,
+                }
+            };
+            let options = SplitOptions {
+                boundary,
+                compression: compress,
+                zip,
+                store,
+                against,
+                ignore_space_check,
+                durability,
+                on_conflict,
+                remove_source,
+                rate_limit: RateLimit::new(rate_limit),
+                ..SplitOptions::new(size.unwrap_or(0))
+            };
+            handle_split(input, options, validate, console)?;
+        }
+        Commands::Join {
+            input,
+            output,
+            mode,
+            ignore_space_check,
+            durability,
+            on_conflict,
+            no_metadata,
+            remove_parts,
+            rate_limit,
+        } => {
+            let options = JoinOptions {
+                mode,
+                ignore_space_check,
+                durability,
+                on_conflict,
+                skip_metadata: no_metadata,
+                remove_parts,
+                rate_limit: RateLimit::new(rate_limit),
+            };
+            handle_join(input, output, options, console)?;
+        }
+        Commands::Cat {
+            input,
+            offset,
+            length,
+            output,
+        } => {
+            handle_cat(input, offset, length, output, console)?;
         }
-        Commands::Join { input, output } => {
-            handle_join(input, output)?;
+        Commands::Rechunk {
+            input,
+            size,
+            output_dir,
+            remove_old,
+            durability,
+        } => {
+            let options = RechunkOptions {
+                part_size: size,
+                output_dir,
+                remove_old,
+                durability,
+            };
+            handle_rechunk(input, options, console)?;
+        }
+        #[cfg(target_os = "linux")]
+        Commands::Mount {
+            input,
+            mountpoint,
+            no_verify,
+        } => {
+            handle_mount(input, mountpoint, !no_verify, console)?;
         }
     }
 
     Ok(())
 }
 
-fn print_banner() {
-    println!(
-        "\n{}",
-        "╔═══════════════════════════════════════╗".bright_blue()
-    );
-    println!(
-        "{}",
-        "║       FILE SPLITTER & JOINER         ║"
-            .bright_blue()
-            .bold()
-    );
-    println!(
-        "{}",
-        "║         Fast • Efficient • Safe       ║".bright_blue()
-    );
-    println!(
-        "{}",
-        "╚═══════════════════════════════════════╝".bright_blue()
-    );
+fn parse_size(text: &str) -> Result<u64, String> {
+    core::parse_size(text).map_err(|e| e.to_string())
+}
+
+fn parse_rate(text: &str) -> Result<u64, String> {
+    rate_limit::parse_rate(text).map_err(|e| e.to_string())
 }
 
-fn handle_split(input: PathBuf, size: u64) -> Result<()> {
-    let splitter = splitter::FileSplitter::new(input, size)?;
+fn handle_split(
+    input: PathBuf,
+    options: SplitOptions,
+    validate: bool,
+    console: Console,
+) -> Result<()> {
+    let splitter = splitter::FileSplitter::new(input, options)?
+        .validate_json(validate)
+        .console(console);
     splitter.split()?;
     Ok(())
 }
 
-fn handle_join(input: PathBuf, output: PathBuf) -> Result<()> {
-    let joiner = joiner::FileJoiner::new(input, output)?;
+fn handle_join(
+    input: PathBuf,
+    output: PathBuf,
+    options: JoinOptions,
+    console: Console,
+) -> Result<()> {
+    let joiner = joiner::FileJoiner::new(input, output, options)?.console(console);
     joiner.join()?;
     Ok(())
 }
+
+fn handle_cat(
+    input: PathBuf,
+    offset: i64,
+    length: Option<u64>,
+    output: Option<PathBuf>,
+    console: Console,
+) -> Result<()> {
+    let extractor = extractor::RangeExtractor::new(input, offset, length, output)?.console(console);
+    extractor.extract()?;
+    Ok(())
+}
+
+fn handle_rechunk(input: PathBuf, options: RechunkOptions, console: Console) -> Result<()> {
+    let rechunker = rechunker::FileRechunker::new(input, options)?.console(console);
+    rechunker.rechunk()?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn handle_mount(
+    input: PathBuf,
+    mountpoint: PathBuf,
+    verify_hashes: bool,
+    console: Console,
+) -> Result<()> {
+    let mount = filesplitter::mount::Mount::new(&input, &mountpoint, verify_hashes)?;
+
+    console.line(format!(
+        "\n{} {:?}",
+        "✓ Mounted:".green().bold(),
+        mount.file_path()
+    ));
+    console.line(format!(
+        "{}\n",
+        "Press Ctrl+C or run `umount` on the mount point to stop.".dimmed()
+    ));
+
+    mount.run(|e| console.warning(format!("{:#}", e)))?;
+    console.line("Unmounted.".green());
+    Ok(())
+}
@@ -0,0 +1,254 @@
+use crate::atomic::{self, AtomicFile};
+use crate::boundary::Boundary;
+use crate::compression::Compression;
+use crate::core::Durability;
+use crate::metadata::FileMetadata;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Version 2 added fields a version 1 build would ignore and then join
+// wrongly, starting with the bytes written around the original data
+// (`prefix_len`/`suffix_len`), so such a build has to refuse the manifest.
+pub const MANIFEST_VERSION: u32 = 2;
+
+// Sidecar written next to the parts (`<name>.manifest`) describing how the
+// original file was cut, so `join` can reverse it exactly.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub file_name: String,
+    pub file_size: u64,
+    pub part_size: u64,
+    #[serde(default, skip_serializing_if = "Boundary::is_bytes")]
+    pub boundary: Boundary,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    // The parts hold a tar stream of a directory, which `join` unpacks.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub directory: bool,
+    // Metadata of the original file, which `join` restores on the output.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<FileMetadata>,
+    // The original file has holes, which `join` leaves as holes again.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub sparse: bool,
+    pub parts: Vec<PartEntry>,
+}
+
+// `offset` and `length` describe the byte range of the original file that
+// the part holds, independent of how the part is encoded on disk.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartEntry {
+    pub name: String,
+    pub offset: u64,
+    pub length: u64,
+    // Bytes written before and after the original data that `join` must drop.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub prefix_len: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub suffix_len: u64,
+    // SHA-256 of the original data in the part, checked by `join`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).context(format!("Failed to open manifest file: {:?}", path))?;
+
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(file))
+            .context(format!("Failed to parse manifest file: {:?}", path))?;
+
+        if manifest.version == 0 || manifest.version > MANIFEST_VERSION {
+            anyhow::bail!(
+                "Unsupported manifest version {} in {:?}; this build reads versions 1 to {}",
+                manifest.version,
+                path,
+                MANIFEST_VERSION
+            );
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn save(&self, path: &Path, durability: Durability) -> Result<()> {
+        let file = AtomicFile::create(path, durability)
+            .context(format!("Failed to create manifest file: {:?}", path))?;
+
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self).context("Failed to write manifest")?;
+        writer
+            .write_all(b"\n")
+            .context("Failed to write manifest")?;
+        atomic::commit_buffered(writer).context(format!("Failed to save manifest: {:?}", path))
+    }
+
+    // Whether part `index` was written for the set whose manifest is at
+    // `path`, i.e. is named `<set>.partNNN` next to it. Unchanged parts a
+    // delta split borrows from an older set, and chunks in a store, belong
+    // to other sets.
+    pub fn owns_part(&self, path: &Path, index: usize) -> bool {
+        let Some(set_name) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".manifest"))
+        else {
+            return false;
+        };
+        self.parts
+            .get(index)
+            .is_some_and(|part| part.name == format!("{}.part{:03}", set_name, index + 1))
+    }
+
+    // Whether any part belongs to another set, so deleting or renaming the
+    // set's parts would break that one.
+    pub fn shares_parts(&self, path: &Path) -> bool {
+        (0..self.parts.len()).any(|index| !self.owns_part(path, index))
+    }
+
+    // Looks up the manifest belonging to a part set, if one was written. A
+    // manifest path is loaded as is.
+    pub fn find_for_part(first_part: &Path) -> Result<Option<Self>> {
+        if is_manifest_path(first_part) {
+            return Ok(Some(Self::load(first_part)?));
+        }
+
+        let path = manifest_path_for_part(first_part)?;
+
+        if path.exists() {
+            Ok(Some(Self::load(&path)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+pub fn manifest_path(input_path: &Path) -> PathBuf {
+    let file_name = input_path
+        .file_name()
+        .unwrap_or_else(|| std::ffi::OsStr::new("file"));
+
+    input_path.with_file_name(format!("{}.manifest", file_name.to_string_lossy()))
+}
+
+pub fn is_manifest_path(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "manifest")
+}
+
+pub fn hex_digest(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// A manifest path is returned as is.
+pub fn manifest_path_for_part(first_part: &Path) -> Result<PathBuf> {
+    if is_manifest_path(first_part) {
+        return Ok(first_part.to_path_buf());
+    }
+
+    let file_name = first_part
+        .file_name()
+        .context("Invalid file name")?
+        .to_string_lossy();
+
+    let base_name = if let Some(pos) = file_name.rfind(".part") {
+        &file_name[..pos]
+    } else {
+        anyhow::bail!("First part file must have .partXXX extension");
+    };
+
+    Ok(first_part.with_file_name(format!("{}.manifest", base_name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_manifest_paths() {
+        assert_eq!(
+            manifest_path(Path::new("dir/app.log")),
+            PathBuf::from("dir/app.log.manifest")
+        );
+        assert_eq!(
+            manifest_path_for_part(Path::new("dir/app.log.part001")).unwrap(),
+            PathBuf::from("dir/app.log.manifest")
+        );
+        assert!(manifest_path_for_part(Path::new("dir/app.log")).is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = Path::new("test_manifest_roundtrip.manifest");
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            file_name: "app.log".to_string(),
+            file_size: 15,
+            part_size: 10,
+            boundary: Boundary::Line,
+            compression: Some(Compression::Zstd),
+            directory: false,
+            metadata: None,
+            sparse: false,
+            parts: vec![
+                PartEntry {
+                    name: "app.log.part001".to_string(),
+                    offset: 0,
+                    length: 10,
+                    prefix_len: 0,
+                    suffix_len: 0,
+                    sha256: None,
+                },
+                PartEntry {
+                    name: "app.log.part002".to_string(),
+                    offset: 10,
+                    length: 5,
+                    prefix_len: 0,
+                    suffix_len: 0,
+                    sha256: None,
+                },
+            ],
+        };
+
+        manifest.save(path, Durability::default()).unwrap();
+        assert_eq!(Manifest::load(path).unwrap(), manifest);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_load_checks_version() {
+        let path = Path::new("test_manifest_version.manifest");
+        let manifest = |version: u32| {
+            format!(
+                r#"{{"version": {}, "file_name": "a", "file_size": 1, "part_size": 1,
+                "parts": [{{"name": "a.part001", "offset": 0, "length": 1}}]}}"#,
+                version
+            )
+        };
+
+        // Written before version 2; the new fields take their defaults.
+        fs::write(path, manifest(1)).unwrap();
+        let loaded = Manifest::load(path).unwrap();
+        assert_eq!(loaded.parts[0].prefix_len, 0);
+
+        for version in [0, MANIFEST_VERSION + 1] {
+            fs::write(path, manifest(version)).unwrap();
+            let error = Manifest::load(path).unwrap_err().to_string();
+            assert!(error.contains("Unsupported manifest version"), "{}", error);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use crate::manifest::hex_digest;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Metadata of the original file, recorded in the manifest so `join` can give
+// the output the same modification time, permissions, ownership and extended
+// attributes. Fields the platform does not have are left out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    // Modification time in nanoseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime_ns: Option<i128>,
+    // Unix permission bits, including setuid/setgid/sticky.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    // Extended attribute values, hex encoded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
+}
+
+impl FileMetadata {
+    pub fn capture(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path).context(format!("Failed to get metadata: {:?}", path))?;
+
+        let mtime_ns = metadata
+            .modified()
+            .ok()
+            .map(|time| match time.duration_since(UNIX_EPOCH) {
+                Ok(after) => after.as_nanos() as i128,
+                Err(before) => -(before.duration().as_nanos() as i128),
+            });
+
+        #[cfg(unix)]
+        let (mode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(metadata.mode() & 0o7777),
+                Some(metadata.uid()),
+                Some(metadata.gid()),
+            )
+        };
+        #[cfg(not(unix))]
+        let (mode, uid, gid) = (None, None, None);
+
+        Ok(Self {
+            mtime_ns,
+            mode,
+            uid,
+            gid,
+            xattrs: read_xattrs(path),
+        })
+    }
+
+    // Permissions and the modification time must be restored; ownership and
+    // extended attributes usually need privileges and are skipped when the
+    // system refuses them.
+    pub fn apply(&self, path: &Path) -> Result<()> {
+        for (name, value) in &self.xattrs {
+            if let Some(value) = decode_hex(value) {
+                let _ = write_xattr(path, name, &value);
+            }
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // Changing the owner clears setuid/setgid, so it goes first.
+            if self.uid.is_some() || self.gid.is_some() {
+                let _ = std::os::unix::fs::chown(path, self.uid, self.gid);
+            }
+            if let Some(mode) = self.mode {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                    .context(format!("Failed to set permissions: {:?}", path))?;
+            }
+        }
+
+        if let Some(mtime_ns) = self.mtime_ns {
+            let offset = Duration::from_nanos(mtime_ns.unsigned_abs() as u64);
+            let mtime = if mtime_ns >= 0 {
+                UNIX_EPOCH + offset
+            } else {
+                UNIX_EPOCH - offset
+            };
+            set_modified(path, mtime)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn set_modified(path: &Path, mtime: SystemTime) -> Result<()> {
+    // Read-only files still accept a new mtime through a handle opened for
+    // reading on Unix; elsewhere the handle needs write access.
+    let file = if cfg!(unix) {
+        File::open(path)
+    } else {
+        File::options().write(true).open(path)
+    };
+
+    file.and_then(|file| file.set_modified(mtime))
+        .context(format!("Failed to set modification time: {:?}", path))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn read_xattrs(path: &Path) -> BTreeMap<String, String> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut xattrs = BTreeMap::new();
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return xattrs;
+    };
+
+    // SAFETY: every buffer handed to listxattr/getxattr is as long as the
+    // size passed with it, and is only read up to the length returned.
+    unsafe {
+        let size = libc::listxattr(c_path.as_ptr(), std::ptr::null_mut(), 0);
+        if size <= 0 {
+            return xattrs;
+        }
+        let mut names = vec![0u8; size as usize];
+        let size = libc::listxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len());
+        if size <= 0 {
+            return xattrs;
+        }
+        names.truncate(size as usize);
+
+        for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+            let Ok(c_name) = CString::new(name) else {
+                continue;
+            };
+            let len = libc::getxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0);
+            if len < 0 {
+                continue;
+            }
+            let mut value = vec![0u8; len as usize];
+            let len = libc::getxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            );
+            if len < 0 {
+                continue;
+            }
+            value.truncate(len as usize);
+            xattrs.insert(
+                String::from_utf8_lossy(name).to_string(),
+                hex_digest(&value),
+            );
+        }
+    }
+
+    xattrs
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_xattrs(_path: &Path) -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+#[cfg(target_os = "linux")]
+fn write_xattr(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let c_name = CString::new(name)?;
+
+    // SAFETY: `value` is valid for `value.len()` bytes.
+    let result = unsafe {
+        libc::setxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            0,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_xattr(_path: &Path, _name: &str, _value: &[u8]) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{self, JoinOptions};
+
+    #[test]
+    fn test_join_restores_mtime_and_mode() {
+        let input = Path::new("test_metadata.bin");
+        fs::write(input, vec![3u8; 5000]).unwrap();
+        let mtime = UNIX_EPOCH + Duration::new(1_500_000_000, 123_456_789);
+        File::options()
+            .write(true)
+            .open(input)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(input, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let parts = core::split_file(input, 2000, |_| {}).unwrap();
+        let output = Path::new("test_metadata_joined.bin");
+        core::join_files(&parts[0], output, |_| {}).unwrap();
+        assert_eq!(fs::metadata(output).unwrap().modified().unwrap(), mtime);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(
+                fs::metadata(output).unwrap().permissions().mode() & 0o7777,
+                0o640
+            );
+        }
+
+        // Without restoring, the output is simply new.
+        fs::remove_file(output).unwrap();
+        let options = JoinOptions {
+            skip_metadata: true,
+            ..JoinOptions::default()
+        };
+        core::join_files_with_options(&parts[0], output, &options, |_| {}).unwrap();
+        assert_ne!(fs::metadata(output).unwrap().modified().unwrap(), mtime);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+        fs::remove_file("test_metadata.bin.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+}
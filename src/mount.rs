@@ -0,0 +1,674 @@
+use crate::part_reader::PartSetReader;
+use anyhow::{Context, Result};
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::UNIX_EPOCH;
+
+// A minimal read-only FUSE file system speaking the kernel protocol over
+// `/dev/fuse` directly: the mount point holds a single file, the original
+// file of a part set, whose reads are served from the parts on demand.
+
+const ROOT_ID: u64 = 1;
+const FILE_ID: u64 = 2;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const MAX_READ: u32 = 128 * 1024;
+// Room for the largest request the kernel may send, header included.
+const BUFFER_SIZE: usize = MAX_READ as usize + 4096;
+const ATTR_VALID_SECS: u64 = 60;
+const FOPEN_KEEP_CACHE: u32 = 1 << 1;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+const IN_HEADER_LEN: usize = 40;
+const OUT_HEADER_LEN: usize = 16;
+
+// Set by SIGINT/SIGTERM so the request loop unmounts and returns.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+pub struct Mount {
+    device: File,
+    mountpoint: PathBuf,
+    reader: PartSetReader,
+    mtime: u64,
+    uid: u32,
+    gid: u32,
+    // Mounted through the setuid `fusermount` helper rather than mount(2).
+    via_helper: bool,
+    // Still mounted, so dropping the `Mount` has to unmount it.
+    mounted: bool,
+}
+
+impl Mount {
+    // Mounts the part set at `mountpoint`. Nothing is served until `run`.
+    pub fn new(first_part: &Path, mountpoint: &Path, verify_hashes: bool) -> Result<Self> {
+        let reader = PartSetReader::open(first_part)?.verify_hashes(verify_hashes);
+
+        if !mountpoint.is_dir() {
+            anyhow::bail!("Mount point is not a directory: {:?}", mountpoint);
+        }
+
+        // Before mounting, so a signal arriving in between still unmounts.
+        install_stop_handler();
+
+        let mtime = std::fs::metadata(first_part)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+
+        let (device, via_helper) = match mount_direct(mountpoint) {
+            Ok(device) => (device, false),
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+                (mount_with_helper(mountpoint)?, true)
+            }
+            Err(e) => {
+                return Err(e).context(format!(
+                    "Failed to mount FUSE file system at {:?}",
+                    mountpoint
+                ))
+            }
+        };
+
+        // SAFETY: getuid/getgid cannot fail.
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+
+        Ok(Self {
+            device,
+            mountpoint: mountpoint.to_path_buf(),
+            reader,
+            mtime,
+            uid,
+            gid,
+            via_helper,
+            mounted: true,
+        })
+    }
+
+    // Path of the joined file inside the mount point.
+    pub fn file_path(&self) -> PathBuf {
+        self.mountpoint.join(self.reader.file_name())
+    }
+
+    // Serves requests until the file system is unmounted (`umount`) or the
+    // process gets SIGINT/SIGTERM, which unmounts it. Reads that fail are
+    // answered with EIO and passed to `on_read_error`; other errors end the
+    // loop and unmount.
+    pub fn run<F>(mut self, mut on_read_error: F) -> Result<()>
+    where
+        F: FnMut(anyhow::Error),
+    {
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+
+        loop {
+            if STOP.load(Ordering::SeqCst) {
+                self.mounted = false;
+                return self.unmount();
+            }
+
+            let len = match self.device.read(&mut buffer) {
+                Ok(len) => len,
+                // Unmounted from outside.
+                Err(e) if e.raw_os_error() == Some(libc::ENODEV) => {
+                    self.mounted = false;
+                    return Ok(());
+                }
+                // ENOENT: the request was interrupted before we read it.
+                Err(e)
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(libc::EINTR | libc::ENOENT | libc::EAGAIN)
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e).context("Failed to read from /dev/fuse"),
+            };
+
+            if len < IN_HEADER_LEN {
+                anyhow::bail!("Short FUSE request ({} bytes)", len);
+            }
+
+            let request = &buffer[..len];
+            let opcode = u32_at(request, 4);
+            let unique = u64_at(request, 8);
+            let node = u64_at(request, 16);
+            let reply = self.handle(opcode, node, &request[IN_HEADER_LEN..], &mut on_read_error);
+
+            if let Some(reply) = reply {
+                self.send(unique, reply)?;
+            }
+
+            // The kernel sends this while unmounting.
+            if opcode == FUSE_DESTROY {
+                self.mounted = false;
+                return Ok(());
+            }
+        }
+    }
+
+    // `None` for requests the kernel expects no reply to.
+    fn handle(
+        &mut self,
+        opcode: u32,
+        node: u64,
+        body: &[u8],
+        on_read_error: &mut dyn FnMut(anyhow::Error),
+    ) -> Option<Result<Vec<u8>, i32>> {
+        Some(match opcode {
+            FUSE_INIT => self.init(body),
+            FUSE_LOOKUP => {
+                let name = body.split(|&b| b == 0).next().unwrap_or_default();
+                if node == ROOT_ID && name == self.reader.file_name().as_bytes() {
+                    Ok(self.entry(FILE_ID))
+                } else {
+                    Err(libc::ENOENT)
+                }
+            }
+            FUSE_GETATTR => match node {
+                ROOT_ID | FILE_ID => {
+                    let mut out = Vec::new();
+                    put_u64(&mut out, ATTR_VALID_SECS);
+                    put_u32(&mut out, 0);
+                    put_u32(&mut out, 0);
+                    self.put_attr(&mut out, node);
+                    Ok(out)
+                }
+                _ => Err(libc::ENOENT),
+            },
+            FUSE_OPEN if node == FILE_ID => Ok(open_out(FOPEN_KEEP_CACHE)),
+            FUSE_OPENDIR if node == ROOT_ID => Ok(open_out(0)),
+            FUSE_OPEN | FUSE_OPENDIR => Err(libc::ENOENT),
+            FUSE_READ if node == FILE_ID => {
+                let offset = u64_at(body, 8);
+                self.read(offset, u32_at(body, 16)).map_err(|e| {
+                    on_read_error(
+                        anyhow::Error::new(e).context(format!("Read at offset {} failed", offset)),
+                    );
+                    libc::EIO
+                })
+            }
+            FUSE_READDIR if node == ROOT_ID => {
+                Ok(self.read_dir(u64_at(body, 8), u32_at(body, 16) as usize))
+            }
+            FUSE_READ | FUSE_READDIR => Err(libc::EBADF),
+            FUSE_STATFS => {
+                let mut out = Vec::new();
+                for _ in 0..5 {
+                    put_u64(&mut out, 0);
+                }
+                put_u32(&mut out, 4096);
+                put_u32(&mut out, 255);
+                put_u32(&mut out, 4096);
+                out.resize(80, 0);
+                Ok(out)
+            }
+            FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_ACCESS | FUSE_DESTROY => {
+                Ok(Vec::new())
+            }
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            _ => Err(libc::ENOSYS),
+        })
+    }
+
+    fn init(&mut self, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let major = u32_at(body, 0);
+        let max_readahead = u32_at(body, 8);
+        if major < FUSE_KERNEL_VERSION {
+            return Err(libc::EPROTO);
+        }
+
+        let mut out = Vec::new();
+        put_u32(&mut out, FUSE_KERNEL_VERSION);
+        put_u32(&mut out, FUSE_KERNEL_MINOR_VERSION);
+        put_u32(&mut out, max_readahead);
+        put_u32(&mut out, 0); // flags
+        out.extend_from_slice(&16u16.to_ne_bytes()); // max_background
+        out.extend_from_slice(&12u16.to_ne_bytes()); // congestion_threshold
+        put_u32(&mut out, MAX_READ); // max_write
+        put_u32(&mut out, 1); // time_gran
+        out.resize(64, 0);
+        Ok(out)
+    }
+
+    fn read(&mut self, offset: u64, size: u32) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(size as usize);
+        self.reader.seek(SeekFrom::Start(offset))?;
+        (&mut self.reader)
+            .take(size as u64)
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn read_dir(&self, offset: u64, size: usize) -> Vec<u8> {
+        let entries: [(u64, &[u8], u32); 3] = [
+            (ROOT_ID, b".", libc::DT_DIR as u32),
+            (ROOT_ID, b"..", libc::DT_DIR as u32),
+            (
+                FILE_ID,
+                self.reader.file_name().as_bytes(),
+                libc::DT_REG as u32,
+            ),
+        ];
+
+        let mut out = Vec::new();
+        for (index, (node, name, kind)) in entries.iter().enumerate().skip(offset as usize) {
+            let entry = dirent(*node, index as u64 + 1, name, *kind);
+            if out.len() + entry.len() > size {
+                break;
+            }
+            out.extend_from_slice(&entry);
+        }
+        out
+    }
+
+    fn entry(&self, node: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_u64(&mut out, node);
+        put_u64(&mut out, 0); // generation
+        put_u64(&mut out, ATTR_VALID_SECS); // entry_valid
+        put_u64(&mut out, ATTR_VALID_SECS); // attr_valid
+        put_u32(&mut out, 0);
+        put_u32(&mut out, 0);
+        self.put_attr(&mut out, node);
+        out
+    }
+
+    fn put_attr(&self, out: &mut Vec<u8>, node: u64) {
+        let (size, mode, nlink) = if node == ROOT_ID {
+            (0, libc::S_IFDIR | 0o555, 2)
+        } else {
+            (self.reader.len(), libc::S_IFREG | 0o444, 1)
+        };
+
+        put_u64(out, node);
+        put_u64(out, size);
+        put_u64(out, size.div_ceil(512)); // blocks
+        for _ in 0..3 {
+            put_u64(out, self.mtime); // atime, mtime, ctime
+        }
+        for _ in 0..3 {
+            put_u32(out, 0); // nanoseconds
+        }
+        put_u32(out, mode);
+        put_u32(out, nlink);
+        put_u32(out, self.uid);
+        put_u32(out, self.gid);
+        put_u32(out, 0); // rdev
+        put_u32(out, 4096); // blksize
+        put_u32(out, 0); // flags
+    }
+
+    fn send(&mut self, unique: u64, reply: Result<Vec<u8>, i32>) -> Result<()> {
+        let (error, payload) = match reply {
+            Ok(payload) => (0, payload),
+            Err(errno) => (-errno, Vec::new()),
+        };
+
+        let mut message = Vec::with_capacity(OUT_HEADER_LEN + payload.len());
+        put_u32(&mut message, (OUT_HEADER_LEN + payload.len()) as u32);
+        message.extend_from_slice(&error.to_ne_bytes());
+        put_u64(&mut message, unique);
+        message.extend_from_slice(&payload);
+
+        // Each reply has to be a single write. ENOENT means the request was
+        // interrupted in the meantime and the reply is simply dropped.
+        match self.device.write(&message) {
+            Ok(_) => Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            Err(e) => Err(e).context("Failed to write to /dev/fuse"),
+        }
+    }
+
+    fn unmount(&self) -> Result<()> {
+        if self.via_helper {
+            let status = helper_command()
+                .arg("-u")
+                .arg(&self.mountpoint)
+                .status()
+                .context("Failed to run fusermount")?;
+            if !status.success() {
+                anyhow::bail!("fusermount could not unmount {:?}", self.mountpoint);
+            }
+            return Ok(());
+        }
+
+        let target = c_path(&self.mountpoint)?;
+        // SAFETY: `target` is a valid NUL-terminated path.
+        if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+            return Err(io::Error::last_os_error())
+                .context(format!("Failed to unmount {:?}", self.mountpoint));
+        }
+        Ok(())
+    }
+}
+
+// Leaves no stale mount behind when `run` returns an error or is never
+// called.
+impl Drop for Mount {
+    fn drop(&mut self) {
+        if self.mounted {
+            let _ = self.unmount();
+        }
+    }
+}
+
+fn mount_direct(mountpoint: &Path) -> io::Result<File> {
+    let device = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/fuse")?;
+
+    // SAFETY: getuid/getgid cannot fail.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let options = format!(
+        "fd={},rootmode=40000,user_id={},group_id={}",
+        device.as_raw_fd(),
+        uid,
+        gid
+    );
+
+    let source = CString::new("filesplitter").unwrap();
+    let fs_type = CString::new("fuse.filesplitter").unwrap();
+    let target = CString::new(mountpoint.as_os_str().as_bytes())
+        .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let data = CString::new(options).unwrap();
+
+    // SAFETY: all pointers are valid NUL-terminated strings for the call.
+    let result = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            fs_type.as_ptr(),
+            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_RDONLY,
+            data.as_ptr().cast(),
+        )
+    };
+
+    if result == 0 {
+        Ok(device)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// Regular users mount through the setuid `fusermount3` helper, which opens
+// `/dev/fuse`, mounts it and passes the descriptor back over a socket named
+// by `_FUSE_COMMFD`.
+fn mount_with_helper(mountpoint: &Path) -> Result<File> {
+    let (ours, theirs) = UnixStream::pair().context("Failed to create socket pair")?;
+
+    // SAFETY: clearing FD_CLOEXEC on a descriptor we own so the helper inherits it.
+    unsafe { libc::fcntl(theirs.as_raw_fd(), libc::F_SETFD, 0) };
+
+    let status = helper_command()
+        .args([
+            "-o",
+            "ro,nosuid,nodev,fsname=filesplitter,subtype=filesplitter",
+            "--",
+        ])
+        .arg(mountpoint)
+        .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+        .status()
+        .context("Mounting as a regular user needs fusermount3 (install fuse3)")?;
+    drop(theirs);
+
+    if !status.success() {
+        anyhow::bail!("fusermount could not mount {:?}", mountpoint);
+    }
+
+    receive_fd(&ours).context("fusermount did not pass back the FUSE device")
+}
+
+fn helper_command() -> Command {
+    let helper = if which("fusermount3") {
+        "fusermount3"
+    } else {
+        "fusermount"
+    };
+    Command::new(helper)
+}
+
+fn which(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).exists()))
+}
+
+fn receive_fd(socket: &UnixStream) -> io::Result<File> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr().cast(),
+        iov_len: byte.len(),
+    };
+    // u64 keeps the control buffer aligned for `cmsghdr`.
+    let mut control = [0u64; 8];
+
+    // SAFETY: an all-zero msghdr is valid; the pointers set below outlive the
+    // recvmsg call, and the control message is checked before it is read.
+    unsafe {
+        let mut message: libc::msghdr = std::mem::zeroed();
+        message.msg_iov = &mut iov;
+        message.msg_iovlen = 1;
+        message.msg_control = control.as_mut_ptr().cast();
+        message.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        if libc::recvmsg(socket.as_raw_fd(), &mut message, 0) <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let header = libc::CMSG_FIRSTHDR(&message);
+        if header.is_null()
+            || (*header).cmsg_level != libc::SOL_SOCKET
+            || (*header).cmsg_type != libc::SCM_RIGHTS
+        {
+            return Err(io::Error::from(io::ErrorKind::InvalidData));
+        }
+
+        let fd = std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<libc::c_int>());
+        Ok(File::from_raw_fd(fd))
+    }
+}
+
+extern "C" fn request_stop(_signal: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+// Without SA_RESTART the blocking read on `/dev/fuse` returns EINTR, so the
+// loop sees the flag right away.
+fn install_stop_handler() {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = request_stop as extern "C" fn(libc::c_int) as usize;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+}
+
+fn c_path(path: &Path) -> Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).context(format!("Invalid path: {:?}", path))
+}
+
+fn open_out(flags: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_u64(&mut out, 0); // fh
+    put_u32(&mut out, flags);
+    put_u32(&mut out, 0);
+    out
+}
+
+// A `fuse_dirent`: the name is padded to a multiple of 8 bytes; `next` is the
+// offset the kernel passes back to continue after this entry.
+fn dirent(node: u64, next: u64, name: &[u8], kind: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_u64(&mut out, node);
+    put_u64(&mut out, next);
+    put_u32(&mut out, name.len() as u32);
+    put_u32(&mut out, kind);
+    out.extend_from_slice(name);
+    out.resize(out.len().next_multiple_of(8), 0);
+    out
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    bytes
+        .get(offset..offset + 4)
+        .map_or(0, |b| u32::from_ne_bytes(b.try_into().unwrap()))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    bytes
+        .get(offset..offset + 8)
+        .map_or(0, |b| u64::from_ne_bytes(b.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A FUSE request: the 40-byte `fuse_in_header`, then `body`.
+    fn request(opcode: u32, unique: u64, node: u64, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        put_u32(&mut out, (IN_HEADER_LEN + body.len()) as u32);
+        put_u32(&mut out, opcode);
+        put_u64(&mut out, unique);
+        put_u64(&mut out, node);
+        out.resize(IN_HEADER_LEN, 0);
+        out.extend_from_slice(body);
+        out
+    }
+
+    // Reads one reply, checks its header and returns the error and payload.
+    fn reply(channel: &mut File, unique: u64) -> (i32, Vec<u8>) {
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        let len = channel.read(&mut buffer).unwrap();
+        assert_eq!(u32_at(&buffer, 0) as usize, len);
+        assert_eq!(u64_at(&buffer, 8), unique);
+        let error = i32::from_ne_bytes(buffer[4..8].try_into().unwrap());
+        (error, buffer[OUT_HEADER_LEN..len].to_vec())
+    }
+
+    #[test]
+    fn test_serves_lookup_getattr_and_read() {
+        let input = Path::new("test_mount_requests.bin");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(input, &data).unwrap();
+        let parts = crate::core::split_file(input, 3000, |_| {}).unwrap();
+
+        // A SOCK_SEQPACKET pair keeps message boundaries, as /dev/fuse does:
+        // each read returns one request.
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for the two descriptors socketpair returns.
+        assert_eq!(
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) },
+            0
+        );
+        // SAFETY: both descriptors were just created and are owned here.
+        let (device, mut channel) =
+            unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+        let mount = Mount {
+            device,
+            mountpoint: PathBuf::from("unused"),
+            reader: PartSetReader::open(&parts[0]).unwrap(),
+            mtime: 0,
+            uid: 0,
+            gid: 0,
+            via_helper: false,
+            mounted: false,
+        };
+
+        let mut read = Vec::new();
+        read.extend_from_slice(&0u64.to_ne_bytes()); // fh
+        read.extend_from_slice(&2990u64.to_ne_bytes()); // offset
+        read.extend_from_slice(&100u32.to_ne_bytes()); // size
+        let requests = [
+            request(FUSE_LOOKUP, 1, ROOT_ID, b"test_mount_requests.bin\0"),
+            request(FUSE_LOOKUP, 2, ROOT_ID, b"other\0"),
+            request(FUSE_GETATTR, 3, FILE_ID, &[0; 16]),
+            request(FUSE_READ, 4, FILE_ID, &read),
+            request(FUSE_DESTROY, 5, ROOT_ID, &[]),
+        ];
+        for request in &requests {
+            channel.write_all(request).unwrap();
+        }
+
+        let mut errors = Vec::new();
+        mount.run(|e| errors.push(e)).unwrap();
+        assert!(errors.is_empty());
+
+        // fuse_entry_out: node id, then the attributes after 40 bytes.
+        let (error, entry) = reply(&mut channel, 1);
+        assert_eq!(error, 0);
+        assert_eq!(u64_at(&entry, 0), FILE_ID);
+        assert_eq!(u64_at(&entry, 48), data.len() as u64);
+
+        assert_eq!(reply(&mut channel, 2).0, -libc::ENOENT);
+
+        // fuse_attr_out: the attributes after 16 bytes.
+        let (error, attr) = reply(&mut channel, 3);
+        assert_eq!(error, 0);
+        assert_eq!(u64_at(&attr, 16), FILE_ID);
+        assert_eq!(u64_at(&attr, 24), data.len() as u64);
+        assert_eq!(u32_at(&attr, 16 + 60), libc::S_IFREG | 0o444);
+
+        // Spans the first two parts.
+        let (error, bytes) = reply(&mut channel, 4);
+        assert_eq!(error, 0);
+        assert_eq!(bytes, data[2990..3090]);
+
+        assert_eq!(reply(&mut channel, 5), (0, Vec::new()));
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_mount_requests.bin.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_dirent_is_padded() {
+        let entry = dirent(FILE_ID, 3, b"backup.tar", libc::DT_REG as u32);
+        assert_eq!(entry.len(), 24 + 16);
+        assert_eq!(u64_at(&entry, 0), FILE_ID);
+        assert_eq!(u64_at(&entry, 8), 3);
+        assert_eq!(u32_at(&entry, 16), 10);
+        assert_eq!(&entry[24..34], b"backup.tar");
+        assert!(entry[34..].iter().all(|&b| b == 0));
+
+        assert_eq!(dirent(ROOT_ID, 1, b".", libc::DT_DIR as u32).len(), 32);
+    }
+}
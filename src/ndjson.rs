@@ -0,0 +1,128 @@
+use crate::core::{ProgressInfo, BUFFER_SIZE};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const MAX_REPORTED_RECORDS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InvalidRecord {
+    pub line: u64,
+    pub offset: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ValidationReport {
+    pub records: u64,
+    pub invalid_count: u64,
+    // Only the first invalid records are kept, so a badly broken export
+    // doesn't have to be held in memory.
+    pub invalid: Vec<InvalidRecord>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.invalid_count == 0
+    }
+}
+
+// Checks that every non-blank line of the file parses as a JSON value.
+pub fn validate_ndjson<F>(input_path: &Path, mut progress_callback: F) -> Result<ValidationReport>
+where
+    F: FnMut(ProgressInfo),
+{
+    let file = File::open(input_path).context("Failed to open input file")?;
+    let file_size = file
+        .metadata()
+        .context("Failed to get file metadata")?
+        .len();
+
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+    let mut report = ValidationReport::default();
+    let mut line = Vec::new();
+    let mut offset = 0u64;
+    let mut line_number = 0u64;
+    let mut last_reported = 0u64;
+
+    loop {
+        line.clear();
+        let bytes_read = reader
+            .read_until(b'\n', &mut line)
+            .context("Failed to read from input file")?;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        line_number += 1;
+
+        if !line.iter().all(u8::is_ascii_whitespace) {
+            report.records += 1;
+
+            if let Err(e) = serde_json::from_slice::<serde::de::IgnoredAny>(&line) {
+                report.invalid_count += 1;
+                if report.invalid.len() < MAX_REPORTED_RECORDS {
+                    report.invalid.push(InvalidRecord {
+                        line: line_number,
+                        offset,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        offset += bytes_read as u64;
+
+        if offset - last_reported >= BUFFER_SIZE as u64 {
+            last_reported = offset;
+            progress_callback(ProgressInfo {
+                current_bytes: offset,
+                total_bytes: file_size,
+                current_part: 0,
+                total_parts: 0,
+                message: "Validating records".to_string(),
+            });
+        }
+    }
+
+    progress_callback(ProgressInfo {
+        current_bytes: file_size,
+        total_bytes: file_size,
+        current_part: 0,
+        total_parts: 0,
+        message: "Validation complete!".to_string(),
+    });
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_validate_reports_malformed_records_with_offsets() {
+        let path = "test_validate.ndjson";
+        fs::write(
+            path,
+            b"{\"id\":1}\n{\"id\":2,}\n\n[1,2,3]\r\n{\"id\":\n\"text\"\n",
+        )
+        .unwrap();
+
+        let report = validate_ndjson(Path::new(path), |_| {}).unwrap();
+
+        assert_eq!(report.records, 5);
+        assert_eq!(report.invalid_count, 2);
+        assert_eq!(report.invalid[0].line, 2);
+        assert_eq!(report.invalid[0].offset, 9);
+        assert_eq!(report.invalid[1].line, 5);
+        assert_eq!(report.invalid[1].offset, 29);
+        assert!(!report.is_valid());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+use crate::compression::{part_decoder, Compression};
+use crate::core::{part_file_name, resolve_parts, BUFFER_SIZE};
+use crate::manifest::{hex_digest, Manifest};
+use crate::zip_split;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// Random access to the original file of a part set without joining it. Parts
+// are opened only when a read reaches them; plain parts are seeked into
+// directly, compressed ones are decoded from the start of the part.
+pub struct PartSetReader {
+    spans: Vec<PartSpan>,
+    compression: Option<Compression>,
+    file_name: String,
+    len: u64,
+    position: u64,
+    current: Option<OpenPart>,
+    // Check each part against its manifest hash before its data is first used.
+    verify_hashes: bool,
+}
+
+// The byte range of the original file held by one part file.
+struct PartSpan {
+    path: PathBuf,
+    offset: u64,
+    length: u64,
+    prefix_len: u64,
+    sha256: Option<String>,
+    verified: bool,
+}
+
+struct OpenPart {
+    index: usize,
+    // Position in the original file that the next read from `reader` returns.
+    position: u64,
+    reader: Box<dyn Read>,
+}
+
+impl PartSetReader {
+    // Accepts the first part, or the manifest, of a part set.
+    pub fn open(first_part: &Path) -> Result<Self> {
+        if !first_part.exists() {
+            anyhow::bail!("First part file does not exist: {:?}", first_part);
+        }
+
+        if zip_split::is_split_zip(first_part) {
+            anyhow::bail!("Split ZIP archives can only be read after joining them");
+        }
+
+        let manifest = Manifest::find_for_part(first_part)?;
+        let part_files = resolve_parts(first_part, manifest.as_ref())?;
+
+        let spans: Vec<PartSpan> = match &manifest {
+            Some(manifest) => part_files
+                .into_iter()
+                .zip(&manifest.parts)
+                .map(|(path, entry)| PartSpan {
+                    path,
+                    offset: entry.offset,
+                    length: entry.length,
+                    prefix_len: entry.prefix_len,
+                    sha256: entry.sha256.clone(),
+                    verified: false,
+                })
+                .collect(),
+            None => {
+                let mut offset = 0;
+                part_files
+                    .into_iter()
+                    .map(|path| {
+                        let length = std::fs::metadata(&path)
+                            .context(format!("Failed to get metadata: {:?}", path))?
+                            .len();
+                        let span = PartSpan {
+                            path,
+                            offset,
+                            length,
+                            prefix_len: 0,
+                            sha256: None,
+                            verified: false,
+                        };
+                        offset += length;
+                        Ok(span)
+                    })
+                    .collect::<Result<_>>()?
+            }
+        };
+
+        let len = spans.last().map_or(0, |span| span.offset + span.length);
+        let file_name = match &manifest {
+            Some(manifest) => manifest.file_name.clone(),
+            None => {
+                let name = part_file_name(first_part);
+                name[..name.rfind(".part").unwrap_or(name.len())].to_string()
+            }
+        };
+
+        Ok(Self {
+            spans,
+            compression: manifest.and_then(|m| m.compression),
+            file_name,
+            len,
+            position: 0,
+            current: None,
+            verify_hashes: false,
+        })
+    }
+
+    // Reading a part for the first time reads all of it once to check its
+    // SHA-256 hash, if the manifest has one.
+    pub fn verify_hashes(mut self, enabled: bool) -> Self {
+        self.verify_hashes = enabled;
+        self
+    }
+
+    // Name of the original file.
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    // Size of the original file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn read_data(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+
+        let index = self
+            .spans
+            .partition_point(|span| span.offset + span.length <= self.position);
+
+        if self.verify_hashes && !self.spans[index].verified {
+            self.verify_part(index)?;
+        }
+
+        let open = match self.current.take() {
+            Some(open) if open.index == index && open.position == self.position => open,
+            _ => self.open_part(index, self.position)?,
+        };
+        let open = self.current.insert(open);
+
+        let span = &self.spans[index];
+        let available = span.offset + span.length - self.position;
+        let to_read = (buf.len() as u64).min(available) as usize;
+        let bytes_read = open
+            .reader
+            .read(&mut buf[..to_read])
+            .context(format!("Failed to read from part file: {:?}", span.path))?;
+
+        if bytes_read == 0 {
+            anyhow::bail!(
+                "Part file {:?} is shorter than the manifest says",
+                span.path
+            );
+        }
+
+        open.position += bytes_read as u64;
+        self.position += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+
+    fn verify_part(&mut self, index: usize) -> Result<()> {
+        let span = &self.spans[index];
+        if let Some(expected) = &span.sha256 {
+            let mut part = self.open_part(index, span.offset)?;
+            let mut hasher = Sha256::new();
+            let hashed = io::copy(&mut part.reader.by_ref().take(span.length), &mut hasher)
+                .context(format!("Failed to read from part file: {:?}", span.path))?;
+
+            if hashed != span.length || hex_digest(&hasher.finalize()) != *expected {
+                anyhow::bail!("Part file {:?} does not match its SHA-256 hash", span.path);
+            }
+        }
+
+        self.spans[index].verified = true;
+        Ok(())
+    }
+
+    // Opens part `index` positioned at `position` of the original file.
+    fn open_part(&self, index: usize, position: u64) -> Result<OpenPart> {
+        let span = &self.spans[index];
+        let mut file =
+            File::open(&span.path).context(format!("Failed to open part file: {:?}", span.path))?;
+        let skip = span.prefix_len + (position - span.offset);
+
+        let reader = match self.compression {
+            None => {
+                file.seek(SeekFrom::Start(skip))
+                    .context(format!("Failed to seek in part file: {:?}", span.path))?;
+                Box::new(BufReader::with_capacity(BUFFER_SIZE, file)) as Box<dyn Read>
+            }
+            Some(_) => {
+                let mut reader = part_decoder(
+                    BufReader::with_capacity(BUFFER_SIZE, file),
+                    self.compression,
+                )?;
+                let skipped = io::copy(&mut (&mut reader).take(skip), &mut io::sink())
+                    .context(format!("Failed to read from part file: {:?}", span.path))?;
+                if skipped != skip {
+                    anyhow::bail!(
+                        "Part file {:?} is shorter than the manifest says",
+                        span.path
+                    );
+                }
+                reader
+            }
+        };
+
+        Ok(OpenPart {
+            index,
+            position,
+            reader,
+        })
+    }
+}
+
+impl Read for PartSetReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_data(buf)
+            .map_err(|e| io::Error::other(format!("{:#}", e)))
+    }
+}
+
+impl Seek for PartSetReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+
+        let Some(target) = target else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            ));
+        };
+
+        self.position = target;
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{self, SplitOptions};
+    use std::fs;
+
+    #[test]
+    fn test_reads_ranges_across_parts() {
+        let input = Path::new("test_part_reader.csv");
+        let mut data = b"id,name\n".to_vec();
+        for i in 0..2000 {
+            data.extend_from_slice(format!("{},row {}\n", i, i).as_bytes());
+        }
+        fs::write(input, &data).unwrap();
+
+        for options in [
+            SplitOptions::new(4000),
+            // Replaces the manifest of the first split.
+            SplitOptions {
+                boundary: crate::boundary::Boundary::Csv,
+                compression: Some(Compression::Zstd),
+                on_conflict: crate::conflict::ConflictPolicy::Overwrite,
+                ..SplitOptions::new(4000)
+            },
+        ] {
+            let parts = core::split_file_with_options(input, &options, |_| {}).unwrap();
+            assert!(parts.len() > 3);
+
+            let mut reader = PartSetReader::open(&parts[0]).unwrap();
+            assert_eq!(reader.len(), data.len() as u64);
+
+            let mut all = Vec::new();
+            reader.read_to_end(&mut all).unwrap();
+            assert_eq!(all, data);
+
+            // A range spanning a part boundary, then seeking backwards.
+            let mut range = vec![0u8; 5000];
+            reader.seek(SeekFrom::Start(3500)).unwrap();
+            reader.read_exact(&mut range).unwrap();
+            assert_eq!(range, data[3500..8500]);
+
+            reader.seek(SeekFrom::End(-10)).unwrap();
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, data[data.len() - 10..]);
+
+            reader.seek(SeekFrom::Start(1)).unwrap();
+            let mut head = [0u8; 6];
+            reader.read_exact(&mut head).unwrap();
+            assert_eq!(&head, b"d,name");
+
+            for part in parts {
+                fs::remove_file(part).unwrap();
+            }
+        }
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_part_reader.csv.manifest").unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use crate::atomic::{self, AtomicFile};
+use crate::compression::{Compression, PartEncoder};
+use crate::core::{
+    get_part_path, part_file_name, Durability, ProgressInfo, BUFFER_SIZE, PROGRESS_INTERVAL,
+};
+use crate::manifest::{hex_digest, PartEntry};
+use crate::rate_limit::RateLimit;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// Writes a byte stream into fixed-size parts, starting a new part file
+// whenever the current one is full.
+pub(crate) struct PartSetWriter<F> {
+    base_path: PathBuf,
+    part_size: u64,
+    compression: Option<Compression>,
+    durability: Durability,
+    sparse: bool,
+    rate_limit: RateLimit,
+    current: Option<PartEncoder<BufWriter<AtomicFile>>>,
+    hasher: Sha256,
+    written_in_part: u64,
+    total_written: u64,
+    last_reported: u64,
+    expected_size: u64,
+    part_paths: Vec<PathBuf>,
+    parts: Vec<PartEntry>,
+    // Progress message, followed by the current part number.
+    label: &'static str,
+    progress_callback: F,
+}
+
+impl<F> PartSetWriter<F>
+where
+    F: FnMut(ProgressInfo),
+{
+    // Parts are named after `base_path` (`<base>.part001`, ...).
+    pub(crate) fn new(
+        base_path: &Path,
+        part_size: u64,
+        compression: Option<Compression>,
+        durability: Durability,
+        expected_size: u64,
+        label: &'static str,
+        progress_callback: F,
+    ) -> Self {
+        Self {
+            base_path: base_path.to_path_buf(),
+            part_size,
+            compression,
+            durability,
+            sparse: false,
+            rate_limit: RateLimit::default(),
+            current: None,
+            hasher: Sha256::new(),
+            written_in_part: 0,
+            total_written: 0,
+            last_reported: 0,
+            expected_size,
+            part_paths: Vec::new(),
+            parts: Vec::new(),
+            label,
+            progress_callback,
+        }
+    }
+
+    // Leaves blocks of zeros in the parts as holes.
+    pub(crate) fn sparse(mut self, sparse: bool) -> Self {
+        self.sparse = sparse;
+        self
+    }
+
+    pub(crate) fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    fn write_data(&mut self, buf: &[u8]) -> Result<usize> {
+        let writer = match self.current.take() {
+            Some(writer) => writer,
+            None => {
+                let part_path = get_part_path(&self.base_path, self.part_paths.len() as u32 + 1);
+                let part_file = AtomicFile::create(&part_path, self.durability)
+                    .context(format!("Failed to create part file: {:?}", part_path))?
+                    .sparse(self.sparse);
+
+                self.part_paths.push(part_path);
+                self.report_progress();
+                PartEncoder::new(
+                    BufWriter::with_capacity(BUFFER_SIZE, part_file),
+                    self.compression,
+                )?
+            }
+        };
+        let writer = self.current.insert(writer);
+
+        let to_write = (self.part_size - self.written_in_part).min(buf.len() as u64) as usize;
+        self.rate_limit.take(to_write as u64);
+        writer
+            .write_all(&buf[..to_write])
+            .context("Failed to write to part file")?;
+        self.hasher.update(&buf[..to_write]);
+
+        self.written_in_part += to_write as u64;
+        self.total_written += to_write as u64;
+
+        if self.written_in_part == self.part_size {
+            self.finish_part()?;
+        }
+
+        if self.total_written - self.last_reported >= PROGRESS_INTERVAL {
+            self.report_progress();
+        }
+
+        Ok(to_write)
+    }
+
+    fn finish_part(&mut self) -> Result<()> {
+        let Some(writer) = self.current.take() else {
+            return Ok(());
+        };
+
+        let part_path = &self.part_paths[self.part_paths.len() - 1];
+        writer
+            .finish()
+            .context("Failed to flush part file")
+            .and_then(atomic::commit_buffered)
+            .context(format!("Failed to write part file: {:?}", part_path))?;
+
+        self.parts.push(PartEntry {
+            name: part_file_name(part_path),
+            offset: self.total_written - self.written_in_part,
+            length: self.written_in_part,
+            prefix_len: 0,
+            suffix_len: 0,
+            sha256: Some(hex_digest(&self.hasher.finalize_reset())),
+        });
+        self.written_in_part = 0;
+
+        Ok(())
+    }
+
+    // Bytes of the stream in parts that have been committed.
+    pub(crate) fn committed_bytes(&self) -> u64 {
+        self.total_written - self.written_in_part
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(Vec<PathBuf>, Vec<PartEntry>, u64)> {
+        self.finish_part()?;
+
+        let total_parts = self.part_paths.len();
+        (self.progress_callback)(ProgressInfo {
+            current_bytes: self.total_written,
+            total_bytes: self.total_written,
+            current_part: total_parts,
+            total_parts,
+            message: "Split complete!".to_string(),
+        });
+
+        Ok((self.part_paths, self.parts, self.total_written))
+    }
+
+    fn report_progress(&mut self) {
+        self.last_reported = self.total_written;
+        (self.progress_callback)(ProgressInfo {
+            current_bytes: self.total_written,
+            total_bytes: self.expected_size.max(self.total_written),
+            current_part: self.part_paths.len(),
+            total_parts: 0,
+            message: format!("{} {}", self.label, self.part_paths.len()),
+        });
+    }
+}
+
+impl<F> Write for PartSetWriter<F>
+where
+    F: FnMut(ProgressInfo),
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.write_data(buf)
+            .map_err(|e| io::Error::other(format!("{:#}", e)))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Longest single sleep, so a new rate set while a job waits applies quickly.
+const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+// A token bucket that caps how many bytes per second a split or join moves.
+// Clones share one bucket, so the rate can be changed from another thread
+// (the GUI) while a job runs. The default is unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // Bytes per second; 0 means unlimited.
+    rate: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    // Bytes that may go out right now; negative after a large buffer, until
+    // enough time has passed to pay it off.
+    tokens: f64,
+    last_refill: Option<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(bytes_per_sec: Option<u64>) -> Self {
+        let limit = Self::default();
+        limit.set(bytes_per_sec);
+        limit
+    }
+
+    pub fn set(&self, bytes_per_sec: Option<u64>) {
+        self.inner
+            .rate
+            .store(bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<u64> {
+        match self.inner.rate.load(Ordering::Relaxed) {
+            0 => None,
+            rate => Some(rate),
+        }
+    }
+
+    // Accounts for `bytes` just moved, sleeping for as long as that takes at
+    // the current rate.
+    pub fn take(&self, bytes: u64) {
+        let mut bucket = self
+            .inner
+            .bucket
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(rate) = self.get() else {
+            bucket.last_refill = None;
+            return;
+        };
+
+        bucket.refill(rate);
+        bucket.tokens -= bytes as f64;
+
+        while bucket.tokens < 0.0 {
+            let Some(rate) = self.get() else {
+                break;
+            };
+            let wait = Duration::from_secs_f64(-bucket.tokens / rate as f64);
+            thread::sleep(wait.min(MAX_SLEEP));
+            bucket.refill(rate);
+        }
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        match self.last_refill {
+            Some(last) => {
+                // At most one second of unused rate is saved up as a burst.
+                let earned = now.duration_since(last).as_secs_f64() * rate as f64;
+                self.tokens = (self.tokens + earned).min(rate as f64);
+            }
+            None => self.tokens = 0.0,
+        }
+        self.last_refill = Some(now);
+    }
+}
+
+// Parses a rate such as `50MiB/s`, `1.5G` or `800K/s`; the `/s` is optional.
+pub fn parse_rate(text: &str) -> Result<u64> {
+    let size = text.trim();
+    let size = size
+        .strip_suffix("/s")
+        .or_else(|| size.strip_suffix("/S"))
+        .unwrap_or(size);
+    let rate = crate::core::parse_size(size)?;
+    if rate == 0 {
+        anyhow::bail!("Rate limit must be greater than 0");
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("50MiB/s").unwrap(), 50 * 1024 * 1024);
+        assert_eq!(parse_rate("800K").unwrap(), 800 * 1024);
+        assert!(parse_rate("0/s").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_take_waits_for_tokens() {
+        let limit = RateLimit::new(Some(1_000_000));
+        let start = Instant::now();
+        for _ in 0..4 {
+            limit.take(50_000);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(150));
+
+        // Lifting the limit stops the waiting.
+        limit.set(None);
+        let start = Instant::now();
+        limit.take(100_000_000);
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
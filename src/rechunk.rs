@@ -0,0 +1,527 @@
+use crate::boundary::Boundary;
+use crate::conflict;
+use crate::core::{joined_size, resolve_parts, Durability, JoinReader, ProgressInfo, BUFFER_SIZE};
+use crate::manifest::{manifest_path, manifest_path_for_part, Manifest, MANIFEST_VERSION};
+use crate::part_writer::PartSetWriter;
+use crate::zip_split;
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct RechunkOptions {
+    pub part_size: u64,
+    // Directory for the new parts; the directory of the old parts by default.
+    pub output_dir: Option<PathBuf>,
+    // Delete each old part once the new parts holding its data are committed,
+    // so the rechunk needs little more free space than one new part.
+    pub remove_old: bool,
+    pub durability: Durability,
+}
+
+// Streams the original data out of an existing part set into parts of a new
+// size, without a joined copy. New parts are written into a temporary
+// directory and moved into place at the end; when they go next to the old
+// parts, the old set is replaced.
+pub fn rechunk_parts<F>(
+    first_part: &Path,
+    options: &RechunkOptions,
+    progress_callback: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: FnMut(ProgressInfo),
+{
+    if options.part_size == 0 {
+        anyhow::bail!("Part size must be greater than 0");
+    }
+
+    if !first_part.exists() {
+        anyhow::bail!("First part file does not exist: {:?}", first_part);
+    }
+
+    if zip_split::is_split_zip(first_part) {
+        anyhow::bail!("Split ZIP archives cannot be rechunked; join them first");
+    }
+
+    let manifest = Manifest::find_for_part(first_part)?;
+    let old_manifest = match &manifest {
+        Some(_) => Some(manifest_path_for_part(first_part)?),
+        None => None,
+    };
+    if let (Some(manifest), Some(old_manifest)) = (&manifest, &old_manifest) {
+        // Chunk stores and delta splits refer to parts that other part sets
+        // still need, and the old parts are deleted or replaced.
+        if manifest.shares_parts(old_manifest) {
+            anyhow::bail!(
+                "{:?} shares parts with another part set and cannot be rechunked; \
+                 join it and split it again instead",
+                old_manifest
+            );
+        }
+        // New parts are plain byte cuts, which would cut through records and
+        // tar members and drop repeated headers.
+        if !manifest.boundary.is_bytes() {
+            anyhow::bail!(
+                "{:?} was cut at record or member boundaries and cannot be rechunked \
+                 into byte cuts; join it and split it again instead",
+                old_manifest
+            );
+        }
+    }
+
+    let old_parts = resolve_parts(first_part, manifest.as_ref())?;
+    let total_size = joined_size(&old_parts, manifest.as_ref());
+
+    let old_dir = first_part.parent().unwrap_or_else(|| Path::new(""));
+    let output_dir = match &options.output_dir {
+        Some(output_dir) => {
+            fs::create_dir_all(output_dir).context(format!(
+                "Failed to create output directory: {:?}",
+                output_dir
+            ))?;
+            output_dir.as_path()
+        }
+        None => old_dir,
+    };
+
+    // The new set keeps the name of the old one, which `--on-conflict rename`
+    // may have made differ from the original file name.
+    let set_name = match &old_manifest {
+        Some(old_manifest) => old_manifest
+            .file_stem()
+            .context("Invalid file name")?
+            .to_string_lossy()
+            .to_string(),
+        None => {
+            let name = first_part.file_name().context("Invalid file name")?;
+            let name = name.to_string_lossy();
+            name[..name.rfind(".part").unwrap_or(name.len())].to_string()
+        }
+    };
+    let file_name = match &manifest {
+        Some(manifest) => manifest.file_name.clone(),
+        None => set_name.clone(),
+    };
+    let compression = manifest.as_ref().and_then(|m| m.compression);
+    let directory = manifest.as_ref().is_some_and(|m| m.directory);
+    let metadata = manifest.as_ref().and_then(|m| m.metadata.clone());
+    let sparse = manifest.as_ref().is_some_and(|m| m.sparse);
+
+    // Files of another set the new parts would be moved over. The old set's
+    // own files are replaced on purpose.
+    let old_files: Vec<&PathBuf> = old_parts.iter().chain(&old_manifest).collect();
+    let conflicts: Vec<PathBuf> =
+        conflict::existing_split_files(&output_dir.join(&set_name), false)?
+            .into_iter()
+            .filter(|file| !old_files.iter().any(|old| same_file(old, file)))
+            .collect();
+    if let Some(existing) = conflicts.first() {
+        anyhow::bail!(
+            "Files of another part set already exist where the new parts go: {:?}",
+            existing
+        );
+    }
+
+    // With --remove-old, new parts left by an interrupted run may hold data
+    // whose old parts are already gone, so they are never thrown away here.
+    let temp_dir = output_dir.join(format!(".{}.rechunk", set_name));
+    if fs::read_dir(&temp_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        anyhow::bail!(
+            "An earlier rechunk was interrupted and left new parts in {:?}; \
+             move them into place or remove the directory first",
+            temp_dir
+        );
+    }
+    fs::create_dir_all(&temp_dir).context(format!(
+        "Failed to create temporary directory: {:?}",
+        temp_dir
+    ))?;
+
+    // An old part is only deleted once its data is synced to the new parts,
+    // whatever the durability setting.
+    let durability = if options.remove_old && options.durability == Durability::None {
+        Durability::Files
+    } else {
+        options.durability
+    };
+    let old_ends = data_ends(&old_parts, manifest.as_ref());
+
+    let mut writer = PartSetWriter::new(
+        &temp_dir.join(&set_name),
+        options.part_size,
+        compression,
+        durability,
+        total_size,
+        "Writing part",
+        progress_callback,
+    )
+    .sparse(sparse && compression.is_none());
+
+    let mut no_progress = |_: ProgressInfo| {};
+    let mut reader = JoinReader::new(&old_parts, manifest.as_ref(), total_size, &mut no_progress);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut removed = 0;
+
+    let copied = (|| -> Result<()> {
+        loop {
+            let bytes_read = reader.read_data(&mut buffer)?;
+            if bytes_read == 0 {
+                return Ok(());
+            }
+            writer
+                .write_all(&buffer[..bytes_read])
+                .context("Failed to write to part file")?;
+
+            // Old parts that have been checked and whose whole range is in
+            // committed new parts.
+            while options.remove_old
+                && removed < reader.parts_read()
+                && old_ends[removed] <= writer.committed_bytes()
+            {
+                remove_part(&old_parts[removed])?;
+                removed += 1;
+            }
+        }
+    })();
+    if let Err(e) = copied {
+        // Drops the uncommitted part; the directory only goes if no committed
+        // part is in it.
+        drop(writer);
+        let _ = fs::remove_dir(&temp_dir);
+        return Err(e);
+    }
+
+    let (temp_parts, parts, file_size) = writer.finish()?;
+
+    // The new parts take the names of the old ones, so the old set has to go
+    // before they are moved into place.
+    let replaces_old = same_dir(output_dir, old_dir)?;
+    if replaces_old || options.remove_old {
+        for part in &old_parts[removed..] {
+            remove_part(part)?;
+        }
+    }
+
+    let mut part_paths = Vec::new();
+    for temp_part in &temp_parts {
+        let part_path = output_dir.join(temp_part.file_name().context("Invalid file name")?);
+        fs::rename(temp_part, &part_path).context(format!(
+            "Failed to move part file into place: {:?}",
+            part_path
+        ))?;
+        part_paths.push(part_path);
+    }
+    fs::remove_dir(&temp_dir).context(format!(
+        "Failed to remove temporary directory: {:?}",
+        temp_dir
+    ))?;
+
+    let new_manifest = manifest_path(&output_dir.join(&set_name));
+    if let Some(old_manifest) = old_manifest {
+        if options.remove_old && !same_file(&old_manifest, &new_manifest) {
+            fs::remove_file(&old_manifest)
+                .context(format!("Failed to remove manifest: {:?}", old_manifest))?;
+        }
+    }
+
+    Manifest {
+        version: MANIFEST_VERSION,
+        file_name,
+        file_size,
+        part_size: options.part_size,
+        boundary: Boundary::Bytes,
+        compression,
+        directory,
+        metadata,
+        sparse,
+        parts,
+    }
+    .save(&new_manifest, options.durability)?;
+
+    Ok(part_paths)
+}
+
+// Where the original data held by each old part ends.
+fn data_ends(old_parts: &[PathBuf], manifest: Option<&Manifest>) -> Vec<u64> {
+    match manifest {
+        Some(manifest) => manifest
+            .parts
+            .iter()
+            .map(|part| part.offset + part.length)
+            .collect(),
+        None => old_parts
+            .iter()
+            .scan(0, |end, part| {
+                *end += fs::metadata(part).map_or(0, |m| m.len());
+                Some(*end)
+            })
+            .collect(),
+    }
+}
+
+fn remove_part(part: &Path) -> Result<()> {
+    fs::remove_file(part).context(format!("Failed to remove part file: {:?}", part))
+}
+
+fn same_dir(a: &Path, b: &Path) -> Result<bool> {
+    let resolve = |dir: &Path| {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        dir.canonicalize()
+            .context(format!("Failed to resolve directory: {:?}", dir))
+    };
+    Ok(resolve(a)? == resolve(b)?)
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conflict::ConflictPolicy;
+    use crate::core::{self, SplitOptions};
+
+    fn options(part_size: u64) -> RechunkOptions {
+        RechunkOptions {
+            part_size,
+            output_dir: None,
+            remove_old: false,
+            durability: Durability::default(),
+        }
+    }
+
+    // Every file under `dir` with its content.
+    fn snapshot(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                files.extend(snapshot(&path));
+            } else {
+                files.push((path.clone(), fs::read(&path).unwrap()));
+            }
+        }
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_sets_sharing_parts_are_refused() {
+        let dir = Path::new("test_rechunk_shared");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let input = dir.join("db.bin");
+        let mut data: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(&input, &data).unwrap();
+
+        // A chunk store, whose chunks other versions' manifests point at.
+        let store = dir.join("store");
+        let cdc = SplitOptions {
+            boundary: Boundary::Cdc,
+            store: Some(store.clone()),
+            ..SplitOptions::new(4096)
+        };
+        let stored = core::split_file_with_report(&input, &cdc, |_| {}).unwrap();
+
+        // A delta set in the same directory, borrowing the base set's parts.
+        core::split_file(&input, 10_000, |_| {}).unwrap();
+        data[12_000] ^= 0xff;
+        fs::write(&input, &data).unwrap();
+        let delta = SplitOptions {
+            against: Some(dir.join("db.bin.manifest")),
+            on_conflict: ConflictPolicy::Rename,
+            ..SplitOptions::new(0)
+        };
+        let delta = core::split_file_with_report(&input, &delta, |_| {}).unwrap();
+
+        let before = snapshot(dir);
+        for manifest in [stored.manifest_path, delta.manifest_path] {
+            let manifest = manifest.unwrap();
+            let error = rechunk_parts(&manifest, &options(5000), |_| {}).unwrap_err();
+            assert!(error.to_string().contains("shares parts"), "{}", error);
+        }
+        assert_eq!(snapshot(dir), before);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_boundaries_are_refused() {
+        let input = Path::new("test_rechunk_records.csv");
+        let mut data = b"id,name\n".to_vec();
+        for i in 0..500 {
+            data.extend_from_slice(format!("{},row {}\n", i, i).as_bytes());
+        }
+        fs::write(input, &data).unwrap();
+        let split = SplitOptions {
+            boundary: Boundary::Csv,
+            ..SplitOptions::new(2000)
+        };
+        let parts = core::split_file_with_options(input, &split, |_| {}).unwrap();
+
+        let error = rechunk_parts(&parts[0], &options(3000), |_| {}).unwrap_err();
+        assert!(error.to_string().contains("boundaries"), "{}", error);
+        assert!(parts.iter().all(|part| part.exists()));
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_rechunk_records.csv.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_renamed_set_keeps_its_name() {
+        let dir = Path::new("test_rechunk_renamed");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let input = dir.join("db.bin");
+        let data: Vec<u8> = (0..12_000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(&input, &data).unwrap();
+        core::split_file(&input, 3000, |_| {}).unwrap();
+        let base = snapshot(dir);
+
+        let rename = SplitOptions {
+            on_conflict: ConflictPolicy::Rename,
+            ..SplitOptions::new(4000)
+        };
+        core::split_file_with_options(&input, &rename, |_| {}).unwrap();
+        let renamed = dir.join("db (1).bin.manifest");
+
+        let parts = rechunk_parts(&renamed, &options(5000), |_| {}).unwrap();
+        assert_eq!(parts[0], dir.join("db (1).bin.part001"));
+        assert_eq!(parts.len(), 3);
+        // The base set is untouched.
+        let files = snapshot(dir);
+        assert!(base.iter().all(|file| files.contains(file)));
+
+        let output = dir.join("joined.bin");
+        core::join_files(&renamed, &output, |_| {}).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data);
+
+        // Moving it next to another set of that name is refused up front.
+        let other = dir.join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("db (1).bin.part001"), b"other").unwrap();
+        let into_other = RechunkOptions {
+            output_dir: Some(other.clone()),
+            ..options(6000)
+        };
+        let error = rechunk_parts(&renamed, &into_other, |_| {}).unwrap_err();
+        assert!(error.to_string().contains("already exist"), "{}", error);
+        assert_eq!(fs::read_dir(&other).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rechunk_in_place_and_into_another_directory() {
+        let input = Path::new("test_rechunk.log");
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(input, &data).unwrap();
+        let old_parts = core::split_file(input, 8000, |_| {}).unwrap();
+        assert_eq!(old_parts.len(), 3);
+
+        // In place: the three old parts become five smaller ones.
+        let options = RechunkOptions {
+            part_size: 4096,
+            output_dir: None,
+            remove_old: true,
+            durability: Durability::default(),
+        };
+        let parts = rechunk_parts(&old_parts[0], &options, |_| {}).unwrap();
+        assert_eq!(parts.len(), 5);
+        assert_eq!(parts[0], PathBuf::from("test_rechunk.log.part001"));
+        assert_eq!(fs::metadata(&parts[0]).unwrap().len(), 4096);
+        let manifest = Manifest::load(Path::new("test_rechunk.log.manifest")).unwrap();
+        assert_eq!(manifest.part_size, 4096);
+        assert_eq!(manifest.parts.len(), 5);
+
+        // Into another directory, keeping the source parts.
+        let out_dir = PathBuf::from("test_rechunk_out");
+        let _ = fs::remove_dir_all(&out_dir);
+        let options = RechunkOptions {
+            part_size: 10_000,
+            output_dir: Some(out_dir.clone()),
+            remove_old: false,
+            durability: Durability::default(),
+        };
+        let moved =
+            rechunk_parts(Path::new("test_rechunk.log.manifest"), &options, |_| {}).unwrap();
+        assert_eq!(moved.len(), 2);
+        assert!(parts.iter().all(|part| part.exists()));
+
+        let output = Path::new("test_rechunk_joined.log");
+        core::join_files(&moved[0], output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), data);
+
+        fs::remove_dir_all(out_dir).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+        fs::remove_file("test_rechunk.log.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remove_old_keeps_parts_until_their_data_is_committed() {
+        let input = Path::new("test_rechunk_bad.bin");
+        let data: Vec<u8> = (0..12_000u32).map(|i| (i % 239) as u8).collect();
+        fs::write(input, &data).unwrap();
+        let old_parts = core::split_file(input, 3000, |_| {}).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::write(&old_parts[2], vec![0u8; 3000]).unwrap();
+
+        // The first new part spans the bad part, so nothing is committed and
+        // nothing is deleted.
+        let options = RechunkOptions {
+            part_size: 10_000,
+            output_dir: None,
+            remove_old: true,
+            durability: Durability::default(),
+        };
+        assert!(rechunk_parts(&old_parts[0], &options, |_| {}).is_err());
+        assert!(old_parts.iter().all(|part| part.exists()));
+        assert!(!Path::new(".test_rechunk_bad.bin.rechunk").exists());
+
+        // With smaller new parts, the old parts before the bad one are
+        // deleted only once their data is in a committed new part.
+        let options = RechunkOptions {
+            part_size: 2000,
+            ..options
+        };
+        assert!(rechunk_parts(&old_parts[0], &options, |_| {}).is_err());
+        assert!(!old_parts[0].exists() && !old_parts[1].exists());
+        let temp_dir = Path::new(".test_rechunk_bad.bin.rechunk");
+        let mut saved = Vec::new();
+        for number in 1..=3 {
+            saved.extend(
+                fs::read(temp_dir.join(format!("test_rechunk_bad.bin.part{:03}", number))).unwrap(),
+            );
+        }
+        assert_eq!(saved, data[..6000]);
+
+        // The new parts of the interrupted run are not thrown away.
+        fs::write(&old_parts[0], &data[..3000]).unwrap();
+        fs::write(&old_parts[1], &data[3000..6000]).unwrap();
+        let error = rechunk_parts(&old_parts[0], &options, |_| {}).unwrap_err();
+        assert!(error.to_string().contains("interrupted"));
+        assert!(temp_dir.join("test_rechunk_bad.bin.part001").exists());
+
+        fs::remove_dir_all(temp_dir).unwrap();
+        fs::remove_file("test_rechunk_bad.bin.manifest").unwrap();
+        for part in old_parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::console::Console;
+use anyhow::Result;
+use colored::{Color, Colorize};
+use filesplitter::core::{self, format_bytes};
+use filesplitter::manifest::Manifest;
+use filesplitter::rechunk::{self, RechunkOptions};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+pub struct FileRechunker {
+    first_part: PathBuf,
+    total_size: u64,
+    old_parts: usize,
+    options: RechunkOptions,
+    console: Console,
+}
+
+impl FileRechunker {
+    pub fn new<P: AsRef<Path>>(first_part: P, options: RechunkOptions) -> Result<Self> {
+        let first_part = first_part.as_ref().to_path_buf();
+
+        if !first_part.exists() {
+            anyhow::bail!("First part file does not exist: {:?}", first_part);
+        }
+
+        if options.part_size == 0 {
+            anyhow::bail!("Part size must be greater than 0");
+        }
+
+        let manifest = Manifest::find_for_part(&first_part)?;
+        let part_files = core::resolve_parts(&first_part, manifest.as_ref())?;
+
+        Ok(Self {
+            total_size: core::joined_size(&part_files, manifest.as_ref()),
+            old_parts: part_files.len(),
+            first_part,
+            options,
+            console: Console::default(),
+        })
+    }
+
+    pub fn console(mut self, console: Console) -> Self {
+        self.console = console;
+        self
+    }
+
+    pub fn rechunk(&self) -> Result<Vec<PathBuf>> {
+        let start = Instant::now();
+        let console = &self.console;
+
+        console.title("          FILE RECHUNKER", Color::BrightCyan);
+        console.blank();
+
+        console.field(
+            "Current parts:",
+            format!(
+                "{} ({})",
+                self.old_parts.to_string().cyan(),
+                format_bytes(self.total_size).yellow()
+            ),
+        );
+        console.field(
+            "New part size:",
+            format_bytes(self.options.part_size).yellow(),
+        );
+        console.field(
+            "New parts:",
+            self.total_size
+                .div_ceil(self.options.part_size)
+                .to_string()
+                .cyan(),
+        );
+        if let Some(output_dir) = &self.options.output_dir {
+            console.field("Output directory:", format!("{:?}", output_dir));
+        }
+        if self.options.remove_old {
+            console.field(
+                "Old parts:",
+                "removed once their data is in committed new parts".yellow(),
+            );
+        }
+        console.blank();
+
+        let progress = console.progress(self.total_size, "cyan");
+
+        let part_paths = rechunk::rechunk_parts(&self.first_part, &self.options, |info| {
+            progress.update(info)
+        })?;
+
+        progress.finish("Rechunk complete!");
+
+        console.success(
+            "✓ Successfully created",
+            format!("{} parts", part_paths.len()).cyan().bold(),
+            Color::BrightCyan,
+        );
+
+        console.part_list("Part files:", &part_paths);
+        console.elapsed(start.elapsed());
+
+        Ok(part_paths)
+    }
+}
@@ -0,0 +1,86 @@
+use clap::ValueEnum;
+use filesplitter::manifest::Manifest;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+// How the CLI reports what it did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    // Banners, colored summaries and progress bars.
+    #[default]
+    Text,
+    // Newline-delimited JSON events on stdout and nothing else.
+    Json,
+}
+
+#[derive(Serialize)]
+struct Event<'a, T> {
+    event: &'a str,
+    #[serde(flatten)]
+    data: T,
+}
+
+// Prints one event as a line of JSON: `{"event": "<event>", ...data}`.
+pub fn emit<T: Serialize>(event: &str, data: T) {
+    match serde_json::to_string(&Event { event, data }) {
+        Ok(line) => println!("{}", line),
+        Err(e) => eprintln!("Failed to encode JSON output: {}", e),
+    }
+}
+
+#[derive(Serialize)]
+pub struct PartReport {
+    pub path: PathBuf,
+    // Size of the part file on disk.
+    pub size: u64,
+    // The range of the original data the part holds, and its SHA-256, as
+    // recorded in the manifest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+// One report per part file, with what the manifest says about it if the set
+// has one.
+pub fn part_reports(part_files: &[PathBuf], manifest: Option<&Manifest>) -> Vec<PartReport> {
+    part_files
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let entry = manifest.and_then(|m| m.parts.get(index));
+            PartReport {
+                path: path.clone(),
+                size: fs::metadata(path).map_or(0, |m| m.len()),
+                offset: entry.map(|e| e.offset),
+                length: entry.map(|e| e.length),
+                sha256: entry.and_then(|e| e.sha256.clone()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_flattens_data() {
+        let part = PartReport {
+            path: PathBuf::from("a.bin.part001"),
+            size: 10,
+            offset: None,
+            length: None,
+            sha256: None,
+        };
+        let line = serde_json::to_string(&Event {
+            event: "part",
+            data: part,
+        })
+        .unwrap();
+        assert_eq!(line, r#"{"event":"part","path":"a.bin.part001","size":10}"#);
+    }
+}
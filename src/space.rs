@@ -0,0 +1,78 @@
+use crate::core::format_bytes;
+use anyhow::Result;
+use std::path::Path;
+
+// Bytes available to this user on the file system that holds `path`, which
+// need not exist yet. `None` where the platform offers no way to ask.
+pub fn available_space(path: &Path) -> Result<Option<u64>> {
+    let mut dir = path;
+    while !dir.exists() {
+        match dir.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => dir = parent,
+            _ => {
+                dir = Path::new(".");
+                break;
+            }
+        }
+    }
+
+    statvfs_available(dir)
+}
+
+// Fails before anything is written when `required` bytes will not fit where
+// `path` goes. Platforms without a free space query always pass.
+pub fn ensure_space(path: &Path, required: u64) -> Result<()> {
+    if let Some(available) = available_space(path)? {
+        if available < required {
+            anyhow::bail!(
+                "Not enough free space for {:?}: {} needed, {} available",
+                path,
+                format_bytes(required),
+                format_bytes(available)
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn statvfs_available(dir: &Path) -> Result<Option<u64>> {
+    use anyhow::Context;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path =
+        CString::new(dir.as_os_str().as_bytes()).context(format!("Invalid path: {:?}", dir))?;
+
+    // SAFETY: `path` is NUL-terminated and `stats` is only read after
+    // statvfs reports success.
+    unsafe {
+        let mut stats: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stats) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .context(format!("Failed to query free space on {:?}", dir));
+        }
+        Ok(Some(stats.f_bavail as u64 * stats.f_frsize as u64))
+    }
+}
+
+#[cfg(not(unix))]
+fn statvfs_available(_dir: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_space() {
+        let path = Path::new("test_space_missing_dir/output.bin");
+        assert!(ensure_space(path, 0).is_ok());
+
+        if let Some(available) = available_space(path).unwrap() {
+            let error = ensure_space(path, available + (1 << 40)).unwrap_err();
+            assert!(error.to_string().contains("Not enough free space"));
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Zero runs shorter than a file system block cannot become holes anyway.
+const BLOCK_SIZE: usize = 4096;
+
+// Reads a file like `File`, but answers reads inside holes with zeros
+// instead of asking the file system for them, so a mostly empty disk image
+// costs little more to read than the data it holds.
+pub(crate) struct SparseReader {
+    file: File,
+    len: u64,
+    pos: u64,
+    // The file's own offset, when it is known.
+    file_pos: Option<u64>,
+    // The region around `pos` found by the last lookup: data or a hole.
+    region: (u64, u64),
+    in_hole: bool,
+}
+
+impl SparseReader {
+    pub(crate) fn new(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            len,
+            pos: 0,
+            file_pos: None,
+            region: (0, 0),
+            in_hole: false,
+        })
+    }
+
+    fn locate(&mut self) -> io::Result<()> {
+        if self.pos >= self.region.0 && self.pos < self.region.1 {
+            return Ok(());
+        }
+        self.region = match next_data(&self.file, self.pos, self.len)? {
+            Some(data) if data > self.pos => {
+                self.in_hole = true;
+                (self.pos, data)
+            }
+            Some(_) => {
+                self.in_hole = false;
+                (self.pos, next_hole(&self.file, self.pos, self.len)?)
+            }
+            // No data after `pos`: the file ends in a hole.
+            None => {
+                self.in_hole = true;
+                (self.pos, self.len)
+            }
+        };
+        self.file_pos = None;
+        Ok(())
+    }
+}
+
+impl Read for SparseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        self.locate()?;
+
+        let available = (self.region.1 - self.pos).min(buf.len() as u64) as usize;
+        if self.in_hole {
+            buf[..available].fill(0);
+            self.pos += available as u64;
+            return Ok(available);
+        }
+
+        if self.file_pos != Some(self.pos) {
+            self.file.seek(SeekFrom::Start(self.pos))?;
+        }
+        let bytes_read = self.file.read(&mut buf[..available])?;
+        self.pos += bytes_read as u64;
+        self.file_pos = Some(self.pos);
+        Ok(bytes_read)
+    }
+}
+
+impl Seek for SparseReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+// Whether the file system reports any hole in the file.
+pub(crate) fn has_holes(file: &File) -> bool {
+    let Ok(metadata) = file.metadata() else {
+        return false;
+    };
+    metadata.len() > 0
+        && matches!(next_hole(file, 0, metadata.len()), Ok(hole) if hole < metadata.len())
+}
+
+// Disk space the file occupies, which for a sparse file is less than its
+// length.
+pub(crate) fn allocated_size(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return 0;
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.len().min(metadata.blocks() * 512)
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+// Writes `buf` at the file's offset, seeking over blocks of zeros instead of
+// writing them so they stay holes. `finish_sparse` gives the file its full
+// length if it ends in such a block.
+pub(crate) fn write_sparse(file: &mut File, buf: &[u8]) -> io::Result<()> {
+    let mut start = 0;
+    while start < buf.len() {
+        let zero = is_zero(&buf[start..(start + BLOCK_SIZE).min(buf.len())]);
+        let mut end = start;
+        while end < buf.len() {
+            let block = &buf[end..(end + BLOCK_SIZE).min(buf.len())];
+            if is_zero(block) != zero {
+                break;
+            }
+            end += block.len();
+        }
+
+        if zero {
+            file.seek(SeekFrom::Current((end - start) as i64))?;
+        } else {
+            file.write_all(&buf[start..end])?;
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+pub(crate) fn finish_sparse(file: &mut File) -> io::Result<()> {
+    let end = file.stream_position()?;
+    if file.metadata()?.len() < end {
+        file.set_len(end)?;
+    }
+    Ok(())
+}
+
+fn is_zero(block: &[u8]) -> bool {
+    block.iter().all(|&b| b == 0)
+}
+
+// Start of the first data at or after `offset`, or `None` when only a hole
+// follows.
+#[cfg(target_os = "linux")]
+fn next_data(file: &File, offset: u64, _len: u64) -> io::Result<Option<u64>> {
+    match seek_raw(file, offset, libc::SEEK_DATA) {
+        Ok(data) => Ok(Some(data)),
+        Err(error) if error.raw_os_error() == Some(libc::ENXIO) => Ok(None),
+        // File systems without hole reporting hold nothing but data.
+        Err(error) if error.raw_os_error() == Some(libc::EINVAL) => Ok(Some(offset)),
+        Err(error) => Err(error),
+    }
+}
+
+// Start of the first hole at or after `offset`; the end of the file counts
+// as one.
+#[cfg(target_os = "linux")]
+fn next_hole(file: &File, offset: u64, len: u64) -> io::Result<u64> {
+    match seek_raw(file, offset, libc::SEEK_HOLE) {
+        Ok(hole) => Ok(hole.min(len)),
+        Err(error) if error.raw_os_error() == Some(libc::EINVAL) => Ok(len),
+        Err(error) => Err(error),
+    }
+}
+
+// SEEK_DATA and SEEK_HOLE move the shared file offset; `SparseReader` seeks
+// back before its next read.
+#[cfg(target_os = "linux")]
+fn seek_raw(file: &File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: lseek only reads the descriptor, which `file` keeps open.
+    let result = unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn next_data(_file: &File, offset: u64, _len: u64) -> io::Result<Option<u64>> {
+    Ok(Some(offset))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn next_hole(_file: &File, _offset: u64, len: u64) -> io::Result<u64> {
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core;
+    use crate::manifest::Manifest;
+    use std::fs;
+
+    #[test]
+    fn test_holes_survive_split_and_join() {
+        let input = Path::new("test_sparse.img");
+        let mut file = File::create(input).unwrap();
+        file.set_len(48 << 20).unwrap();
+        file.seek(SeekFrom::Start(1 << 20)).unwrap();
+        file.write_all(&[7u8; 10_000]).unwrap();
+        file.seek(SeekFrom::Start(40 << 20)).unwrap();
+        file.write_all(&[9u8; 10_000]).unwrap();
+        drop(file);
+
+        let parts = core::split_file(input, 16 << 20, |_| {}).unwrap();
+        let output = Path::new("test_sparse_joined.img");
+        core::join_files(&parts[0], output, |_| {}).unwrap();
+        assert_eq!(fs::read(output).unwrap(), fs::read(input).unwrap());
+
+        // Where the file system reports holes, nothing of them is allocated.
+        if has_holes(&File::open(input).unwrap()) {
+            assert!(
+                Manifest::load(Path::new("test_sparse.img.manifest"))
+                    .unwrap()
+                    .sparse
+            );
+            assert!(allocated_size(&parts[1]) < 1 << 20);
+            assert!(allocated_size(output) < 4 << 20);
+        }
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+        fs::remove_file("test_sparse.img.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+}
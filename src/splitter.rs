@@ -1,171 +1,392 @@
+use crate::console::Console;
+use crate::report::{self, PartReport};
 use anyhow::{Context, Result};
-use colored::Colorize;
-use indicatif::{ProgressBar, ProgressStyle};
+use colored::{Color, Colorize};
+use filesplitter::boundary::Boundary;
+use filesplitter::conflict::ConflictPolicy;
+use filesplitter::core::{self, format_bytes, SplitOptions};
+use filesplitter::manifest::Manifest;
+use filesplitter::{cdc, directory, ndjson, zip_split};
+use serde::Serialize;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-
-const BUFFER_SIZE: usize = 8 * 1024 * 1024; // 8MB buffer for fast I/O
+use std::time::Instant;
 
 pub struct FileSplitter {
     input_path: PathBuf,
-    part_size: u64,
+    options: SplitOptions,
+    validate_json: bool,
+    console: Console,
+}
+
+// The result of `split --output json`.
+#[derive(Serialize)]
+struct SplitReport {
+    input: PathBuf,
+    input_size: u64,
+    // Existing parts were kept under `--on-conflict skip`; nothing was written.
+    skipped: bool,
+    manifest: Option<PathBuf>,
+    // Part files written by this run; a delta split reuses the others.
+    parts_written: usize,
+    parts: Vec<PartReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<ndjson::ValidationReport>,
+    source_removed: bool,
+    elapsed_secs: f64,
 }
 
 impl FileSplitter {
-    pub fn new<P: AsRef<Path>>(input_path: P, part_size: u64) -> Result<Self> {
+    pub fn new<P: AsRef<Path>>(input_path: P, mut options: SplitOptions) -> Result<Self> {
         let input_path = input_path.as_ref().to_path_buf();
 
         if !input_path.exists() {
             anyhow::bail!("Input file does not exist: {:?}", input_path);
         }
 
-        if part_size == 0 {
+        // A delta split reuses the part size of the split it is compared to.
+        if let (Some(against), 0) = (&options.against, options.part_size) {
+            options.part_size = Manifest::load(against)?.part_size;
+        }
+
+        if options.part_size == 0 && options.boundary.uses_part_size() {
             anyhow::bail!("Part size must be greater than 0");
         }
 
         Ok(Self {
             input_path,
-            part_size,
+            options,
+            validate_json: false,
+            console: Console::default(),
         })
     }
 
-    pub fn split(&self) -> Result<Vec<PathBuf>> {
-        let file = File::open(&self.input_path).context("Failed to open input file")?;
+    pub fn console(mut self, console: Console) -> Self {
+        self.console = console;
+        self
+    }
 
-        let file_size = file
-            .metadata()
-            .context("Failed to get file metadata")?
-            .len();
+    // Check every record parses as JSON before splitting (NDJSON input).
+    pub fn validate_json(mut self, enabled: bool) -> Self {
+        self.validate_json = enabled;
+        self
+    }
 
-        println!(
-            "\n{}",
-            "═══════════════════════════════════════".bright_cyan()
-        );
-        println!("{}", "          FILE SPLITTER".bright_cyan().bold());
-        println!(
-            "{}",
-            "═══════════════════════════════════════".bright_cyan()
-        );
+    pub fn split(&self) -> Result<Vec<PathBuf>> {
+        if self.console.is_json() {
+            return self.split_json();
+        }
 
-        println!("\n{} {:?}", "Input file:".green().bold(), self.input_path);
-        println!(
-            "{} {}",
-            "File size:".green().bold(),
-            format_bytes(file_size).yellow()
-        );
-        println!(
-            "{} {}",
-            "Part size:".green().bold(),
-            format_bytes(self.part_size).yellow()
-        );
+        let start = Instant::now();
+        let console = &self.console;
+        let is_directory = self.input_path.is_dir();
+        let file_size = self.input_size()?;
+
+        console.title("          FILE SPLITTER", Color::BrightCyan);
+        console.blank();
+
+        if is_directory {
+            console.field("Input directory:", format!("{:?}", self.input_path));
+            console.field(
+                "Contents size:",
+                format!(
+                    "{} (archived as {:?})",
+                    format_bytes(file_size).yellow(),
+                    directory::archive_path(&self.input_path)?
+                ),
+            );
+        } else {
+            console.field("Input file:", format!("{:?}", self.input_path));
+            console.field("File size:", format_bytes(file_size).yellow());
+        }
+        match &self.options.boundary {
+            Boundary::Lines { count } => {
+                console.field("Lines per part:", count.to_string().yellow())
+            }
+            boundary => {
+                let label = if *boundary == Boundary::Cdc {
+                    "Average chunk size:"
+                } else {
+                    "Part size:"
+                };
+                console.field(label, format_bytes(self.options.part_size).yellow());
+                let description = match boundary {
+                    Boundary::Line => Some("line (each part ends at a newline)".to_string()),
+                    Boundary::Csv => {
+                        Some("csv (whole records, header repeated in every part)".to_string())
+                    }
+                    Boundary::Ndjson => Some("ndjson (whole records)".to_string()),
+                    Boundary::Tar => Some("tar (each part is an extractable archive)".to_string()),
+                    Boundary::Cdc => Some(format!(
+                        "cdc (content-defined chunks, stored in {:?})",
+                        self.options.store.as_deref().unwrap_or(Path::new("."))
+                    )),
+                    Boundary::Delimiter { delimiter } => Some(format!(
+                        "after delimiter {:?}",
+                        String::from_utf8_lossy(delimiter)
+                    )),
+                    Boundary::Regex { pattern } => {
+                        Some(format!("before lines matching {:?}", pattern))
+                    }
+                    _ => None,
+                };
+                if let Some(description) = description {
+                    console.field("Boundary:", description.yellow());
+                }
+            }
+        }
+        if self.options.zip {
+            console.field(
+                "Format:",
+                format!(
+                    "split ZIP ({:?} and .z01, .z02, ...)",
+                    zip_split::zip_path(&self.input_path)?
+                )
+                .yellow(),
+            );
+        }
+        if let Some(against) = &self.options.against {
+            console.field(
+                "Against:",
+                format!("{:?} (unchanged parts are not written)", against).yellow(),
+            );
+        }
+        if let Some(compression) = self.options.compression {
+            console.field(
+                "Compression:",
+                format!("{:?} (one frame per part)", compression).yellow(),
+            );
+        }
+        if let Some(rate) = self.options.rate_limit.get() {
+            console.field("Rate limit:", format!("{}/s", format_bytes(rate)).yellow());
+        }
 
-        let num_parts = file_size.div_ceil(self.part_size);
-        println!(
-            "{} {}\n",
-            "Total parts:".green().bold(),
-            num_parts.to_string().cyan()
-        );
+        if self.options.boundary.is_bytes() && !is_directory && !self.options.zip {
+            let num_parts = file_size.div_ceil(self.options.part_size);
+            console.field("Total parts:", num_parts.to_string().cyan());
+        }
+        let existing = core::split_conflicts(&self.input_path, &self.options)?;
+        if !existing.is_empty() {
+            console.field(
+                "Existing files:",
+                format!(
+                    "{} from an earlier split ({})",
+                    existing.len().to_string().cyan(),
+                    conflict_action(self.options.on_conflict).yellow()
+                ),
+            );
+        }
+        console.blank();
+
+        if !existing.is_empty() && self.options.on_conflict == ConflictPolicy::Skip {
+            console.line(format!(
+                "{}\n",
+                "✓ Skipped: the existing parts are kept".green().bold()
+            ));
+            return core::split_file_with_options(&self.input_path, &self.options, |_| {});
+        }
 
-        let pb = ProgressBar::new(file_size);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:50.cyan/blue}] {bytes}/{total_bytes} ({percent}%) {msg} ETA: {eta}")
-                .unwrap()
-                .progress_chars("█▓▒░ "),
-        );
+        if self.validate_json {
+            self.validate_records(file_size)?;
+        }
 
-        let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
-        let mut part_paths = Vec::new();
-        let mut part_number = 1;
-        let mut total_written = 0u64;
+        let progress = console.progress(file_size, "cyan");
 
-        while total_written < file_size {
-            let part_path = self.get_part_path(part_number);
-            let part_file = File::create(&part_path)
-                .context(format!("Failed to create part file: {:?}", part_path))?;
+        if let (Boundary::Cdc, Some(store)) = (&self.options.boundary, &self.options.store) {
+            let report = cdc::split_to_store(&self.input_path, store, &self.options, |info| {
+                progress.update(info)
+            })?;
+            if self.options.remove_source {
+                core::remove_split_source(&self.input_path, &report.manifest_path, |info| {
+                    progress.update(info)
+                })?;
+            }
+            progress.finish("Split complete!");
+            self.print_store_report(&report);
+            self.print_source_removed();
+            console.elapsed(start.elapsed());
+            return Ok(report.chunks);
+        }
 
-            let mut writer = BufWriter::with_capacity(BUFFER_SIZE, part_file);
-            let mut bytes_written_in_part = 0u64;
-            let mut buffer = vec![0u8; BUFFER_SIZE];
+        let report = core::split_file_with_report(&self.input_path, &self.options, |info| {
+            progress.update(info)
+        })?;
+        let part_paths = report.parts;
 
-            pb.set_message(format!("Splitting part {}/{}", part_number, num_parts));
+        progress.finish("Split complete!");
 
-            while bytes_written_in_part < self.part_size && total_written < file_size {
-                let remaining_in_part = self.part_size - bytes_written_in_part;
-                let to_read = (BUFFER_SIZE as u64).min(remaining_in_part) as usize;
+        console.success(
+            "✓ Successfully created",
+            format!("{} parts", part_paths.len()).cyan().bold(),
+            Color::BrightCyan,
+        );
 
-                let bytes_read = reader
-                    .read(&mut buffer[..to_read])
-                    .context("Failed to read from input file")?;
+        if let (Some(_), Some(path)) = (&self.options.against, &report.manifest_path) {
+            let manifest = Manifest::load(path)?;
+            console.line(format!(
+                "\n{} {}",
+                "Unchanged parts:".green().bold(),
+                (manifest.parts.len() - part_paths.len()).to_string().cyan()
+            ));
+        }
 
-                if bytes_read == 0 {
-                    break;
-                }
+        console.part_list("Part files:", &part_paths);
+        self.print_source_removed();
+        console.elapsed(start.elapsed());
 
-                writer
-                    .write_all(&buffer[..bytes_read])
-                    .context("Failed to write to part file")?;
+        Ok(part_paths)
+    }
 
-                bytes_written_in_part += bytes_read as u64;
-                total_written += bytes_read as u64;
-                pb.set_position(total_written);
+    fn split_json(&self) -> Result<Vec<PathBuf>> {
+        let start = Instant::now();
+        let input_size = self.input_size()?;
+        let progress = self.console.progress(input_size, "cyan");
+        let progress = |info| progress.update(info);
+
+        let existing = core::split_conflicts(&self.input_path, &self.options)?;
+        let skipped = !existing.is_empty() && self.options.on_conflict == ConflictPolicy::Skip;
+
+        let validation = if self.validate_json && !skipped {
+            Some(ndjson::validate_ndjson(&self.input_path, progress)?)
+        } else {
+            None
+        };
+        let (part_paths, manifest) = match (&self.options.boundary, &self.options.store) {
+            (Boundary::Cdc, Some(store)) if !skipped => {
+                let report = cdc::split_to_store(&self.input_path, store, &self.options, progress)?;
+                if self.options.remove_source {
+                    core::remove_split_source(&self.input_path, &report.manifest_path, progress)?;
+                }
+                (report.chunks, Some(report.manifest_path))
+            }
+            _ => {
+                let report =
+                    core::split_file_with_report(&self.input_path, &self.options, progress)?;
+                (report.parts, report.manifest_path)
             }
+        };
+
+        // A manifest lists the whole set, including parts a delta split reused.
+        let parts = match &manifest {
+            Some(path) => {
+                let loaded = Manifest::load(path)?;
+                let part_files = core::resolve_parts(path, Some(&loaded))?;
+                report::part_reports(&part_files, Some(&loaded))
+            }
+            None => report::part_reports(&part_paths, None),
+        };
+
+        report::emit(
+            "result",
+            SplitReport {
+                input: self.input_path.clone(),
+                input_size,
+                skipped,
+                manifest,
+                parts_written: if skipped { 0 } else { part_paths.len() },
+                parts,
+                validation,
+                source_removed: self.options.remove_source && !skipped,
+                elapsed_secs: start.elapsed().as_secs_f64(),
+            },
+        );
 
-            writer.flush().context("Failed to flush part file")?;
+        Ok(part_paths)
+    }
 
-            part_paths.push(part_path);
-            part_number += 1;
+    fn input_size(&self) -> Result<u64> {
+        if self.input_path.is_dir() {
+            directory::tree_size(&self.input_path)
+        } else {
+            Ok(File::open(&self.input_path)
+                .context("Failed to open input file")?
+                .metadata()
+                .context("Failed to get file metadata")?
+                .len())
         }
+    }
 
-        pb.finish_with_message("Split complete!".green().to_string());
+    fn print_source_removed(&self) {
+        if self.options.remove_source {
+            self.console.line(format!(
+                "{} {:?}\n",
+                "✓ Removed the source:".green().bold(),
+                self.input_path
+            ));
+        }
+    }
 
-        println!(
-            "\n{}",
-            "═══════════════════════════════════════".bright_cyan()
+    fn print_store_report(&self, report: &cdc::StoreReport) {
+        let console = &self.console;
+        console.success(
+            "✓ Successfully stored",
+            format!("{} chunks", report.chunks.len()).cyan().bold(),
+            Color::BrightCyan,
         );
-        println!(
-            "{} {}",
-            "✓ Successfully created".green().bold(),
-            format!("{} parts", part_paths.len()).cyan().bold()
+        console.blank();
+        console.field(
+            "New chunks:",
+            format!(
+                "{} ({})",
+                report.new_chunks.to_string().cyan(),
+                format_bytes(report.new_bytes).yellow()
+            ),
         );
-        println!(
-            "{}",
-            "═══════════════════════════════════════".bright_cyan()
+        console.field(
+            "Already stored:",
+            (report.chunks.len() - report.new_chunks).to_string().cyan(),
         );
-
-        println!("\n{}", "Part files:".yellow().bold());
-        for (i, part) in part_paths.iter().enumerate() {
-            println!("  {} {:?}", format!("[{}]", i + 1).cyan(), part);
-        }
-        println!();
-
-        Ok(part_paths)
+        console.field("Manifest:", format!("{:?}\n", report.manifest_path));
     }
 
-    fn get_part_path(&self, part_number: u32) -> PathBuf {
-        let file_name = self
-            .input_path
-            .file_name()
-            .unwrap_or_else(|| std::ffi::OsStr::new("file"));
+    fn validate_records(&self, file_size: u64) -> Result<()> {
+        let console = &self.console;
+        let progress = console.progress(file_size, "yellow");
+        let report = ndjson::validate_ndjson(&self.input_path, |info| progress.update(info))?;
+        progress.clear();
+
+        if report.is_valid() {
+            console.line(format!(
+                "{} {}\n",
+                "✓ Valid records:".green().bold(),
+                report.records.to_string().cyan()
+            ));
+            return Ok(());
+        }
 
-        let part_name = format!("{}.part{:03}", file_name.to_string_lossy(), part_number);
+        console.warning(format!(
+            "{} of {} records are not valid JSON",
+            report.invalid_count.to_string().red().bold(),
+            report.records
+        ));
+        for record in &report.invalid {
+            console.line(format!(
+                "  {} line {}, offset {}: {}",
+                "✗".red(),
+                record.line,
+                record.offset,
+                record.error
+            ));
+        }
+        if report.invalid_count > report.invalid.len() as u64 {
+            console.line(format!(
+                "  ... and {} more",
+                report.invalid_count - report.invalid.len() as u64
+            ));
+        }
+        console.blank();
 
-        self.input_path.with_file_name(part_name)
+        Ok(())
     }
 }
 
-fn format_bytes(bytes: u64) -> String {
-    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB"];
-    let mut size = bytes as f64;
-    let mut unit_index = 0;
-
-    while size >= 1024.0 && unit_index < UNITS.len() - 1 {
-        size /= 1024.0;
-        unit_index += 1;
+pub fn conflict_action(policy: ConflictPolicy) -> &'static str {
+    match policy {
+        ConflictPolicy::Fail => "stopping; choose an --on-conflict policy",
+        ConflictPolicy::Overwrite => "will be replaced",
+        ConflictPolicy::Skip => "kept, nothing is written",
+        ConflictPolicy::Rename => "kept, the new files get another name",
     }
-
-    format!("{:.2} {}", size, UNITS[unit_index])
 }
 
 #[cfg(test)]
@@ -183,9 +404,20 @@ mod tests {
         assert_eq!(format_bytes(1099511627776), "1.00 TB");
     }
 
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(core::parse_size("4294967296").unwrap(), 4294967296);
+        assert_eq!(core::parse_size("512K").unwrap(), 512 * 1024);
+        assert_eq!(core::parse_size("100MB").unwrap(), 100 * 1024 * 1024);
+        assert_eq!(core::parse_size("2G").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert_eq!(core::parse_size("1.5gib").unwrap(), 3 * 512 * 1024 * 1024);
+        assert!(core::parse_size("12 parsecs").is_err());
+        assert!(core::parse_size("G").is_err());
+    }
+
     #[test]
     fn test_new_validates_file_exists() {
-        let result = FileSplitter::new("nonexistent_file.txt", 1024);
+        let result = FileSplitter::new("nonexistent_file.txt", SplitOptions::new(1024));
         assert!(result.is_err());
     }
 
@@ -197,7 +429,7 @@ mod tests {
             .write_all(b"test")
             .unwrap();
 
-        let result = FileSplitter::new(temp_file, 0);
+        let result = FileSplitter::new(temp_file, SplitOptions::new(0));
         assert!(result.is_err());
 
         fs::remove_file(temp_file).unwrap();
@@ -213,7 +445,7 @@ mod tests {
             .write_all(&test_data)
             .unwrap();
 
-        let splitter = FileSplitter::new(test_file, 3000).unwrap();
+        let splitter = FileSplitter::new(test_file, SplitOptions::new(3000)).unwrap();
         let parts = splitter.split().unwrap();
 
         assert_eq!(parts.len(), 4);
@@ -223,6 +455,7 @@ mod tests {
         }
 
         fs::remove_file(test_file).unwrap();
+        fs::remove_file("test_split_file.bin.manifest").unwrap();
         for part in parts {
             fs::remove_file(part).unwrap();
         }
@@ -0,0 +1,686 @@
+use crate::boundary::PartCut;
+use anyhow::{Context, Result};
+use std::io::{Read, Seek, SeekFrom};
+
+const BLOCK_SIZE: u64 = 512;
+const END_OF_ARCHIVE_LEN: u64 = 2 * BLOCK_SIZE;
+const MIN_PART_SIZE: u64 = 8 * BLOCK_SIZE;
+
+const NAME_FIELD_SIZE: usize = 100;
+const GNU_MAGIC: &[u8; 8] = b"ustar  \0";
+const PAX_GLOBAL_NAME: &[u8] = b"././@PaxHeader";
+// Long names and pax records are read into memory; anything larger is taken
+// for a corrupt header rather than allocated.
+const MAX_EXTENSION_SIZE: u64 = 1024 * 1024;
+
+// One archive member, including any GNU long-name or pax extension headers
+// that precede it and must stay in the same part.
+struct Entry {
+    start: u64,
+    data_start: u64,
+    size: u64,
+    name: Vec<u8>,
+}
+
+impl Entry {
+    fn end(&self) -> u64 {
+        self.data_start + round_up(self.size)
+    }
+}
+
+// A member whose data did not fit in the previous part and continues in the
+// next one behind a GNU multi-volume (`M`) header.
+struct Continuation {
+    name: Vec<u8>,
+    data_start: u64,
+    size: u64,
+}
+
+// Cuts a tar archive only between members, so every part is a complete
+// archive of its own: parts get a fresh end-of-archive marker appended. A
+// member too large for any part starts a part of its own and is continued GNU
+// multi-volume style; the parts it spans are a multi-volume archive for
+// `tar -M` instead, ending with the first one that has an end marker.
+pub(crate) struct TarCutter {
+    part_size: u64,
+    file_size: u64,
+    continuation: Option<Continuation>,
+}
+
+impl TarCutter {
+    pub fn new(part_size: u64, file_size: u64) -> Result<Self> {
+        if part_size < MIN_PART_SIZE {
+            anyhow::bail!(
+                "Part size must be at least {} bytes for tar archives",
+                MIN_PART_SIZE
+            );
+        }
+
+        Ok(Self {
+            part_size,
+            file_size,
+            continuation: None,
+        })
+    }
+
+    pub fn next_part<R: Read + Seek>(&mut self, scan: &mut R, start: u64) -> Result<PartCut> {
+        let prefix = match &self.continuation {
+            Some(continuation) => {
+                let prefix = continuation_headers(continuation, start);
+                if prefix.len() as u64 + BLOCK_SIZE > self.part_size {
+                    anyhow::bail!(
+                        "Part size {} is too small to continue tar member {:?}",
+                        self.part_size,
+                        String::from_utf8_lossy(&continuation.name)
+                    );
+                }
+                prefix
+            }
+            None => Vec::new(),
+        };
+        let limit = start + self.part_size - prefix.len() as u64;
+
+        // The last part keeps the archive's own end-of-archive blocks.
+        if self.file_size <= limit {
+            self.continuation = None;
+            return Ok(PartCut {
+                end: self.file_size,
+                prefix,
+                suffix: Vec::new(),
+            });
+        }
+
+        let trailer_limit = limit - END_OF_ARCHIVE_LEN;
+        let mut boundary = None;
+        let mut position = start;
+
+        if let Some(continuation) = self.continuation.take() {
+            let entry_end = continuation.data_start + round_up(continuation.size);
+            if entry_end > trailer_limit {
+                let end = block_floor(limit).min(entry_end - BLOCK_SIZE);
+                self.continuation = Some(continuation);
+                return Ok(PartCut {
+                    end,
+                    prefix,
+                    suffix: Vec::new(),
+                });
+            }
+            boundary = Some(entry_end);
+            position = entry_end;
+        }
+
+        loop {
+            let Some(entry) = read_entry(scan, position)? else {
+                break;
+            };
+
+            if entry.end() <= trailer_limit {
+                boundary = Some(entry.end());
+                position = entry.end();
+                continue;
+            }
+
+            // A member that does not fit after the ones already here starts
+            // the next part, so this one stays a complete archive. One too
+            // large even for an empty part is continued GNU multi-volume
+            // style from the start of a part.
+            if boundary.is_some() {
+                break;
+            }
+            let end = block_floor(limit).min(entry.end() - BLOCK_SIZE);
+            if end <= entry.data_start {
+                anyhow::bail!(
+                    "Part size is too small for the tar headers at offset {}",
+                    entry.start
+                );
+            }
+            self.continuation = Some(Continuation {
+                name: entry.name,
+                data_start: entry.data_start,
+                size: entry.size,
+            });
+            return Ok(PartCut {
+                end,
+                prefix,
+                suffix: Vec::new(),
+            });
+        }
+
+        Ok(match boundary {
+            Some(end) => PartCut {
+                end,
+                prefix,
+                suffix: vec![0u8; END_OF_ARCHIVE_LEN as usize],
+            },
+            // Only end-of-archive padding is left; it is copied as is.
+            None => PartCut {
+                end: block_floor(limit),
+                prefix,
+                suffix: Vec::new(),
+            },
+        })
+    }
+}
+
+// Reads the member starting at `position`, or `None` at the end-of-archive
+// marker.
+fn read_entry<R: Read + Seek>(scan: &mut R, position: u64) -> Result<Option<Entry>> {
+    let mut header = [0u8; BLOCK_SIZE as usize];
+    let mut header_pos = position;
+    let mut long_name = None;
+    let mut pax_path = None;
+    let mut pax_size = None;
+
+    loop {
+        scan.seek(SeekFrom::Start(header_pos))
+            .context("Failed to seek in input file")?;
+        scan.read_exact(&mut header)
+            .context(format!("Truncated tar header at offset {}", header_pos))?;
+
+        if header.iter().all(|&b| b == 0) {
+            if header_pos != position {
+                anyhow::bail!("Tar member at offset {} has no header", position);
+            }
+            return Ok(None);
+        }
+
+        if !checksum_matches(&header) {
+            anyhow::bail!(
+                "Not a tar archive: invalid header checksum at offset {}",
+                header_pos
+            );
+        }
+
+        let size = parse_numeric(&header[124..136]).context(format!(
+            "Invalid size in tar header at offset {}",
+            header_pos
+        ))?;
+        let data_start = header_pos + BLOCK_SIZE;
+
+        match header[156] {
+            b'L' => {
+                let mut name = read_data(scan, data_start, size)?;
+                trim_nuls(&mut name);
+                long_name = Some(name);
+            }
+            b'K' => {}
+            b'x' => {
+                let records = read_data(scan, data_start, size)?;
+                for (key, value) in pax_records(&records) {
+                    match key {
+                        b"path" => pax_path = Some(value.to_vec()),
+                        b"size" => {
+                            pax_size = std::str::from_utf8(value)
+                                .ok()
+                                .and_then(|v| v.parse::<u64>().ok());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            typeflag => {
+                // Links, devices, directories and FIFOs store no data.
+                let size = if (b'1'..=b'6').contains(&typeflag) {
+                    0
+                } else {
+                    pax_size.unwrap_or(size)
+                };
+                let name = pax_path
+                    .or(long_name)
+                    .unwrap_or_else(|| header_name(&header));
+
+                return Ok(Some(Entry {
+                    start: position,
+                    data_start,
+                    size,
+                    name,
+                }));
+            }
+        }
+
+        header_pos = data_start + round_up(size);
+    }
+}
+
+fn read_data<R: Read + Seek>(scan: &mut R, position: u64, size: u64) -> Result<Vec<u8>> {
+    if size > MAX_EXTENSION_SIZE {
+        anyhow::bail!(
+            "Tar extension header at offset {} is too large ({} bytes)",
+            position - BLOCK_SIZE,
+            size
+        );
+    }
+    let mut data = vec![0u8; size as usize];
+    scan.seek(SeekFrom::Start(position))
+        .context("Failed to seek in input file")?;
+    scan.read_exact(&mut data)
+        .context(format!("Truncated tar member at offset {}", position))?;
+    Ok(data)
+}
+
+fn header_name(header: &[u8]) -> Vec<u8> {
+    let mut name = header[..NAME_FIELD_SIZE].to_vec();
+    trim_nuls(&mut name);
+
+    // ustar splits long paths into a prefix and a name.
+    if &header[257..263] == b"ustar\0" && header[345] != 0 {
+        let mut prefix = header[345..500].to_vec();
+        trim_nuls(&mut prefix);
+        prefix.push(b'/');
+        prefix.extend_from_slice(&name);
+        return prefix;
+    }
+
+    name
+}
+
+fn pax_records(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut records = Vec::new();
+    let mut rest = data;
+
+    while let Some(space) = rest.iter().position(|&b| b == b' ') {
+        let Some(len) = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|l| l.parse::<usize>().ok())
+            .filter(|&l| l > space + 1 && l <= rest.len())
+        else {
+            break;
+        };
+
+        let record = &rest[space + 1..len - 1];
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            records.push((&record[..eq], &record[eq + 1..]));
+        }
+        rest = &rest[len..];
+    }
+
+    records
+}
+
+// GNU tar's continuation header: the member name, the bytes still to come,
+// and the offset they start at. A name too long for the header is given in
+// full by a pax global header before it, as GNU tar does for its own volumes;
+// otherwise tar refuses the truncated name as "not continued on this volume".
+fn continuation_headers(continuation: &Continuation, start: u64) -> Vec<u8> {
+    let offset = start - continuation.data_start;
+    let remaining = continuation.size - offset;
+    let mut headers = Vec::new();
+
+    if continuation.name.len() > NAME_FIELD_SIZE {
+        let mut records = pax_record("GNU.volume.filename", &continuation.name);
+        records.extend(pax_record(
+            "GNU.volume.size",
+            remaining.to_string().as_bytes(),
+        ));
+        records.extend(pax_record(
+            "GNU.volume.offset",
+            offset.to_string().as_bytes(),
+        ));
+        headers.extend(gnu_header(PAX_GLOBAL_NAME, b'g', records.len() as u64, 0));
+        let len = headers.len() + records.len();
+        headers.extend(records);
+        headers.resize(round_up(len as u64) as usize, 0);
+    }
+    headers.extend(gnu_header(&continuation.name, b'M', remaining, offset));
+
+    headers
+}
+
+// One `<length> <key>=<value>\n` pax record, where the length counts itself.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let body = key.len() + value.len() + 3;
+    let mut len = body + body.to_string().len();
+    if len.to_string().len() + body > len {
+        len += 1;
+    }
+
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+// A GNU header holding just a name (truncated to the name field), type, size
+// and multi-volume offset.
+fn gnu_header(name: &[u8], typeflag: u8, size: u64, offset: u64) -> Vec<u8> {
+    let mut header = vec![0u8; BLOCK_SIZE as usize];
+
+    let name_len = name.len().min(NAME_FIELD_SIZE);
+    header[..name_len].copy_from_slice(&name[..name_len]);
+    write_numeric(&mut header[124..136], size);
+    header[156] = typeflag;
+    header[257..265].copy_from_slice(GNU_MAGIC);
+    if typeflag == b'M' {
+        write_numeric(&mut header[369..381], offset);
+    }
+
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+    header
+}
+
+fn checksum_matches(header: &[u8]) -> bool {
+    let Some(expected) = parse_numeric(&header[148..156]) else {
+        return false;
+    };
+
+    let field = 148..156;
+    let unsigned: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if field.contains(&i) {
+                b' ' as u64
+            } else {
+                b as u64
+            }
+        })
+        .sum();
+    let signed: i64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if field.contains(&i) {
+                b' ' as i64
+            } else {
+                b as i8 as i64
+            }
+        })
+        .sum();
+
+    expected == unsigned || expected as i64 == signed
+}
+
+// Octal, or GNU base-256 when the high bit of the first byte is set.
+fn parse_numeric(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7f), |acc, &b| {
+                acc.checked_mul(256).map(|acc| acc + b as u64)
+            });
+    }
+
+    let text = std::str::from_utf8(field).ok()?;
+    let digits = text.trim_matches(|c: char| c == ' ' || c == '\0');
+    if digits.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+fn write_numeric(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+
+    if octal.len() <= digits {
+        field[..digits].copy_from_slice(octal.as_bytes());
+        field[digits] = 0;
+    } else {
+        field.fill(0);
+        let bytes = value.to_be_bytes();
+        let len = field.len();
+        field[len - bytes.len()..].copy_from_slice(&bytes);
+        field[0] |= 0x80;
+    }
+}
+
+fn trim_nuls(bytes: &mut Vec<u8>) {
+    if let Some(end) = bytes.iter().position(|&b| b == 0) {
+        bytes.truncate(end);
+    }
+}
+
+fn round_up(size: u64) -> u64 {
+    size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+fn block_floor(position: u64) -> u64 {
+    position / BLOCK_SIZE * BLOCK_SIZE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn header(name: &str, typeflag: u8, size: u64) -> Vec<u8> {
+        let mut header = vec![0u8; BLOCK_SIZE as usize];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        write_numeric(&mut header[124..136], size);
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].fill(b' ');
+        let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        header
+    }
+
+    fn archive(sizes: &[u64]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (i, &size) in sizes.iter().enumerate() {
+            data.extend(header(&format!("file{}", i), b'0', size));
+            data.extend(vec![b'a' + i as u8; size as usize]);
+            data.resize(round_up(data.len() as u64) as usize, 0);
+        }
+        data.extend(vec![0u8; 2 * BLOCK_SIZE as usize]);
+        data
+    }
+
+    fn cuts(data: &[u8], part_size: u64) -> Vec<PartCut> {
+        let mut scan = Cursor::new(data);
+        let mut cutter = TarCutter::new(part_size, data.len() as u64).unwrap();
+        let mut cuts = Vec::new();
+        let mut start = 0;
+
+        while start < data.len() as u64 {
+            let cut = cutter.next_part(&mut scan, start).unwrap();
+            start = cut.end;
+            cuts.push(cut);
+        }
+
+        cuts
+    }
+
+    #[test]
+    fn test_numeric_fields() {
+        let mut field = [0u8; 12];
+        write_numeric(&mut field, 0o1234);
+        assert_eq!(&field, b"00000001234\0");
+        assert_eq!(parse_numeric(&field), Some(0o1234));
+
+        write_numeric(&mut field, 10 << 33);
+        assert_eq!(field[0] & 0x80, 0x80);
+        assert_eq!(parse_numeric(&field), Some(10 << 33));
+    }
+
+    #[test]
+    fn test_cuts_between_members_and_appends_end_marker() {
+        let data = archive(&[1000, 1000, 1000, 1000]);
+
+        // Each member takes 1536 bytes; two fit with the end marker in 4096.
+        let cuts = cuts(&data, 4096);
+        assert_eq!(
+            cuts.iter().map(|c| c.end).collect::<Vec<_>>(),
+            vec![3072, data.len() as u64]
+        );
+        assert_eq!(cuts[0].suffix.len(), 1024);
+        assert!(cuts[0].prefix.is_empty());
+        assert!(cuts[1].suffix.is_empty());
+    }
+
+    // Extracts `parts` with GNU tar into a fresh `dir`, as one multi-volume
+    // archive when there are several.
+    fn extract(dir: &std::path::Path, parts: &[std::path::PathBuf]) -> bool {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).unwrap();
+        let mut tar = std::process::Command::new("tar");
+        tar.arg("-x").arg("-C").arg(dir);
+        if parts.len() > 1 {
+            tar.arg("-M");
+        }
+        for part in parts {
+            tar.arg("-f").arg(part);
+        }
+        tar.stdin(std::process::Stdio::null())
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    // Reports the skip when there is no GNU tar to check the parts with.
+    fn gnu_tar_available() -> bool {
+        let available = std::process::Command::new("tar")
+            .arg("--version")
+            .output()
+            .is_ok_and(|out| String::from_utf8_lossy(&out.stdout).contains("GNU"));
+        if !available {
+            eprintln!("skipped: GNU tar not found");
+        }
+        available
+    }
+
+    #[test]
+    fn test_oversized_member_parts_extract_with_tar() {
+        use crate::boundary::Boundary;
+        use crate::core::{self, SplitOptions};
+        use std::fs;
+        use std::path::Path;
+
+        if !gnu_tar_available() {
+            return;
+        }
+
+        let input = Path::new("test_tar_oversized.tar");
+        let sizes = [100, 10000, 100, 700];
+        fs::write(input, archive(&sizes)).unwrap();
+        let options = SplitOptions {
+            boundary: Boundary::Tar,
+            ..SplitOptions::new(4096)
+        };
+        let parts = core::split_file_with_options(input, &options, |_| {}).unwrap();
+
+        // The big member starts a part of its own, and the parts it spans
+        // extract together as a multi-volume archive; the others on their own.
+        assert_eq!(parts.len(), 6);
+        let dir = Path::new("test_tar_oversized_extract");
+        let groups: [(&[std::path::PathBuf], &[usize]); 3] = [
+            (&parts[..1], &[0]),
+            (&parts[1..5], &[1, 2]),
+            (&parts[5..], &[3]),
+        ];
+        for (group, members) in groups {
+            assert!(extract(dir, group), "tar failed on {:?}", group);
+            let mut extracted: Vec<_> = fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            extracted.sort();
+            assert_eq!(extracted.len(), members.len());
+            for &i in members {
+                let content = fs::read(dir.join(format!("file{}", i))).unwrap();
+                assert_eq!(content, vec![b'a' + i as u8; sizes[i] as usize]);
+            }
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_tar_oversized.tar.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_long_name_continues_with_tar() {
+        use crate::boundary::Boundary;
+        use crate::core::{self, SplitOptions};
+        use std::fs;
+        use std::path::Path;
+
+        if !gnu_tar_available() {
+            return;
+        }
+
+        // A member whose name only fits in a GNU long-name record.
+        let name = "n".repeat(150);
+        let mut data = header("././@LongLink", b'L', name.len() as u64 + 1);
+        data.extend(name.as_bytes());
+        data.resize(round_up(data.len() as u64 + 1) as usize, 0);
+        data.extend(header(&name[..NAME_FIELD_SIZE], b'0', 10000));
+        data.extend(vec![b'a'; 10000]);
+        data.resize(round_up(data.len() as u64) as usize, 0);
+        data.extend(vec![0u8; 2 * BLOCK_SIZE as usize]);
+
+        let input = Path::new("test_tar_long_name.tar");
+        fs::write(input, &data).unwrap();
+        let options = SplitOptions {
+            boundary: Boundary::Tar,
+            ..SplitOptions::new(4096)
+        };
+        let parts = core::split_file_with_options(input, &options, |_| {}).unwrap();
+        assert!(parts.len() > 2);
+
+        let dir = Path::new("test_tar_long_name_extract");
+        assert!(extract(dir, &parts), "tar failed on {:?}", parts);
+        assert_eq!(fs::read(dir.join(&name)).unwrap(), vec![b'a'; 10000]);
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_tar_long_name.tar.manifest").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_oversized_extension_header_is_refused() {
+        let mut data = header("././@LongLink", b'L', MAX_EXTENSION_SIZE + 1);
+        data.extend(vec![0u8; 2 * BLOCK_SIZE as usize]);
+
+        // Claims a larger archive, so the cutter has to scan for a boundary.
+        let mut scan = Cursor::new(&data);
+        let mut cutter = TarCutter::new(4096, 1 << 20).unwrap();
+        let error = match cutter.next_part(&mut scan, 0) {
+            Ok(_) => panic!("an oversized long-name record was read"),
+            Err(error) => error.to_string(),
+        };
+        assert!(error.contains("too large"), "{}", error);
+    }
+
+    #[test]
+    fn test_split_and_join_round_trip() {
+        use crate::boundary::Boundary;
+        use crate::core::{self, SplitOptions};
+        use std::fs;
+        use std::path::Path;
+
+        let input = Path::new("test_tar_split.tar");
+        let data = archive(&[3000, 700, 9000, 1200, 50]);
+        fs::write(input, &data).unwrap();
+
+        let options = SplitOptions {
+            boundary: Boundary::Tar,
+            ..SplitOptions::new(4096)
+        };
+        let parts = core::split_file_with_options(input, &options, |_| {}).unwrap();
+        assert!(parts.len() > 2);
+        for part in &parts {
+            assert!(fs::metadata(part).unwrap().len() <= 4096);
+        }
+
+        core::join_files(&parts[0], Path::new("test_tar_split_output.tar"), |_| {}).unwrap();
+        assert_eq!(fs::read("test_tar_split_output.tar").unwrap(), data);
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file("test_tar_split.tar.manifest").unwrap();
+        fs::remove_file("test_tar_split_output.tar").unwrap();
+        for part in parts {
+            fs::remove_file(part).unwrap();
+        }
+    }
+}
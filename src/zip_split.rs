@@ -0,0 +1,1450 @@
+use crate::atomic::{self, sync_dir, temp_path, AtomicFile};
+use crate::core::{
+    civil_from_unix, format_bytes, parent_dir, Durability, ProgressInfo, BUFFER_SIZE,
+};
+use crate::directory;
+use crate::rate_limit::RateLimit;
+use anyhow::{Context, Result};
+use flate2::write::DeflateEncoder;
+use flate2::Crc;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// The ZIP spec doesn't allow split segments smaller than 64KiB.
+pub const MIN_SEGMENT_SIZE: u64 = 64 * 1024;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+// Starts the first segment of a split archive; `PK00` replaces it when the
+// archive ended up in a single segment.
+const SPLIT_SIGNATURE: u32 = 0x0807_4b50;
+const SINGLE_SEGMENT_SIGNATURE: u32 = 0x3030_4b50;
+
+const END_OF_CENTRAL_DIR_LEN: usize = 22;
+const ZIP64_END_OF_CENTRAL_DIR_LEN: usize = 56;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const MAX_COMMENT_LEN: usize = 0xFFFF;
+
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const TIMESTAMP_EXTRA_ID: u16 = 0x5455;
+
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+const VERSION_MADE_BY: u16 = (3 << 8) | 45; // Unix, spec 4.5
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+// Files at least this large get zip64 sizes up front, leaving room for
+// deflate's worst-case expansion below the 4GiB limit.
+const ZIP64_THRESHOLD: u64 = 0xF000_0000;
+
+// Writes `input` (a file or a directory) as a standard split ZIP:
+// `<name>.z01`, `<name>.z02`, ..., `<name>.zip` (named after `zip_path`),
+// each at most `segment_size` bytes. Reassembled with `join` (or
+// `zip -s 0`) it is an ordinary ZIP.
+pub fn split_zip<F>(
+    input_path: &Path,
+    zip_path: &Path,
+    segment_size: u64,
+    durability: Durability,
+    rate_limit: &RateLimit,
+    mut progress_callback: F,
+) -> Result<Vec<PathBuf>>
+where
+    F: FnMut(ProgressInfo),
+{
+    if segment_size < MIN_SEGMENT_SIZE {
+        anyhow::bail!(
+            "Part size must be at least {} for split ZIP archives",
+            format_bytes(MIN_SEGMENT_SIZE)
+        );
+    }
+
+    let entries = collect_entries(input_path)?;
+    let total_size: u64 = entries.iter().map(|e| e.size).sum();
+    let mut writer = SegmentWriter::new(zip_path, segment_size, durability)?;
+    let mut records = Vec::with_capacity(entries.len());
+    let mut bytes_read = 0u64;
+
+    for entry in &entries {
+        let record = write_entry(&mut writer, entry, &mut |read, current_part| {
+            rate_limit.take(read);
+            bytes_read += read;
+            progress_callback(ProgressInfo {
+                current_bytes: bytes_read,
+                total_bytes: total_size,
+                current_part,
+                total_parts: 0,
+                message: format!("Compressing {}", entry.name),
+            });
+        })?;
+        records.push(record);
+    }
+
+    write_central_directory(&mut writer, &records)?;
+    let segments = writer.finish()?;
+
+    progress_callback(ProgressInfo {
+        current_bytes: total_size,
+        total_bytes: total_size,
+        current_part: segments.len(),
+        total_parts: segments.len(),
+        message: "Split complete!".to_string(),
+    });
+
+    Ok(segments)
+}
+
+// `data.bin` becomes `data.bin.zip`, `photos/` becomes `photos.zip`.
+pub fn zip_path(input_path: &Path) -> Result<PathBuf> {
+    if input_path.is_dir() {
+        return Ok(directory::archive_path(input_path)?.with_extension("zip"));
+    }
+
+    let file_name = input_path.file_name().context("Invalid file name")?;
+    Ok(input_path.with_file_name(format!("{}.zip", file_name.to_string_lossy())))
+}
+
+// A segment of a split ZIP: `archive.z01` (the first), or `archive.zip` when
+// `archive.z01` exists next to it or it is a split archive that fit in one
+// segment.
+pub fn is_split_zip(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("z01") => true,
+        Some(ext) if ext.eq_ignore_ascii_case("zip") => {
+            path.with_extension("z01").exists() || is_single_segment(path)
+        }
+        _ => false,
+    }
+}
+
+fn is_single_segment(path: &Path) -> bool {
+    let mut marker = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut marker))
+        .is_ok()
+        && u32::from_le_bytes(marker) == SINGLE_SEGMENT_SIGNATURE
+}
+
+// All segments in disk order, ending with the `.zip`.
+pub fn find_segments(path: &Path) -> Result<Vec<PathBuf>> {
+    let mut segments = Vec::new();
+
+    for number in 1.. {
+        let segment = path.with_extension(format!("z{:02}", number));
+        if !segment.exists() {
+            break;
+        }
+        segments.push(segment);
+    }
+
+    let last = path.with_extension("zip");
+    if !last.exists() || (segments.is_empty() && !is_single_segment(&last)) {
+        anyhow::bail!(
+            "Incomplete split ZIP: expected {:?} and {:?}",
+            path.with_extension("z01"),
+            last
+        );
+    }
+    segments.push(last);
+
+    Ok(segments)
+}
+
+enum EntryKind {
+    File,
+    Directory,
+    Symlink(Vec<u8>),
+}
+
+struct SourceEntry {
+    path: PathBuf,
+    name: String,
+    kind: EntryKind,
+    size: u64,
+    mode: u32,
+    mtime: u64,
+}
+
+// A central directory record, with offsets relative to the segment (disk)
+// the local header was written to.
+struct CentralRecord {
+    name: String,
+    flags: u16,
+    method: u16,
+    mtime: u64,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    disk: u32,
+    offset: u64,
+    external_attributes: u32,
+}
+
+fn collect_entries(input_path: &Path) -> Result<Vec<SourceEntry>> {
+    let mut entries = Vec::new();
+    let metadata =
+        fs::metadata(input_path).context(format!("Failed to get metadata: {:?}", input_path))?;
+
+    if metadata.is_dir() {
+        let archive = directory::archive_path(input_path)?;
+        let root = archive
+            .file_stem()
+            .context("Invalid directory name")?
+            .to_string_lossy()
+            .to_string();
+        collect_directory(input_path, &root, &mut entries)?;
+    } else {
+        let name = input_path
+            .file_name()
+            .context("Invalid file name")?
+            .to_string_lossy()
+            .to_string();
+        entries.push(source_entry(input_path, name, &metadata)?);
+    }
+
+    Ok(entries)
+}
+
+fn collect_directory(dir: &Path, name: &str, entries: &mut Vec<SourceEntry>) -> Result<()> {
+    let metadata = fs::metadata(dir).context(format!("Failed to get metadata: {:?}", dir))?;
+    entries.push(source_entry(dir, format!("{}/", name), &metadata)?);
+
+    let mut children = fs::read_dir(dir)
+        .context(format!("Failed to read directory: {:?}", dir))?
+        .collect::<std::io::Result<Vec<_>>>()
+        .context(format!("Failed to read directory: {:?}", dir))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let path = child.path();
+        let child_name = format!("{}/{}", name, child.file_name().to_string_lossy());
+        let metadata =
+            fs::symlink_metadata(&path).context(format!("Failed to get metadata: {:?}", path))?;
+
+        if metadata.is_dir() {
+            collect_directory(&path, &child_name, entries)?;
+        } else if metadata.is_file() || metadata.file_type().is_symlink() {
+            entries.push(source_entry(&path, child_name, &metadata)?);
+        }
+    }
+
+    Ok(())
+}
+
+fn source_entry(path: &Path, name: String, metadata: &fs::Metadata) -> Result<SourceEntry> {
+    let (kind, size) = if metadata.is_dir() {
+        (EntryKind::Directory, 0)
+    } else if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).context(format!("Failed to read link: {:?}", path))?;
+        let target = target.to_string_lossy().as_bytes().to_vec();
+        let size = target.len() as u64;
+        (EntryKind::Symlink(target), size)
+    } else {
+        (EntryKind::File, metadata.len())
+    };
+
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+
+    Ok(SourceEntry {
+        path: path.to_path_buf(),
+        name,
+        kind,
+        size,
+        mode: unix_mode(metadata),
+        mtime,
+    })
+}
+
+#[cfg(unix)]
+fn unix_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mode()
+}
+
+#[cfg(not(unix))]
+fn unix_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o040755
+    } else {
+        0o100644
+    }
+}
+
+fn write_entry(
+    writer: &mut SegmentWriter,
+    entry: &SourceEntry,
+    progress: &mut dyn FnMut(u64, usize),
+) -> Result<CentralRecord> {
+    let flags = if entry.name.is_ascii() { 0 } else { FLAG_UTF8 };
+    let mut external_attributes = entry.mode << 16;
+    if let EntryKind::Directory = entry.kind {
+        external_attributes |= 0x10; // MS-DOS directory attribute
+    }
+
+    // Directories and symlinks are small and stored; their CRC and sizes go
+    // straight into the local header.
+    let stored = match &entry.kind {
+        EntryKind::File => None,
+        EntryKind::Directory => Some(Vec::new()),
+        EntryKind::Symlink(target) => Some(target.clone()),
+    };
+
+    if let Some(data) = stored {
+        let mut crc = Crc::new();
+        crc.update(&data);
+        let mut record = CentralRecord {
+            name: entry.name.clone(),
+            flags,
+            method: METHOD_STORED,
+            mtime: entry.mtime,
+            crc: crc.sum(),
+            compressed_size: data.len() as u64,
+            size: data.len() as u64,
+            disk: 0,
+            offset: 0,
+            external_attributes,
+        };
+
+        let header = local_header(&record, false);
+        writer.ensure_room(header.len() as u64)?;
+        (record.disk, record.offset) = writer.position();
+        writer
+            .write_all(&header)
+            .context("Failed to write ZIP segment")?;
+        writer
+            .write_all(&data)
+            .context("Failed to write ZIP segment")?;
+        progress(data.len() as u64, writer.position().0 as usize + 1);
+        return Ok(record);
+    }
+
+    // Regular files are deflated as they are read. The local header is
+    // rewritten with the CRC and sizes afterwards, even if its segment is
+    // already complete: data descriptors in split archives trip up some
+    // tools.
+    let zip64 = entry.size >= ZIP64_THRESHOLD;
+    let mut record = CentralRecord {
+        name: entry.name.clone(),
+        flags,
+        method: METHOD_DEFLATED,
+        mtime: entry.mtime,
+        crc: 0,
+        compressed_size: 0,
+        size: 0,
+        disk: 0,
+        offset: 0,
+        external_attributes,
+    };
+
+    let header = local_header(&record, zip64);
+    writer.ensure_room(header.len() as u64)?;
+    (record.disk, record.offset) = writer.position();
+    writer
+        .write_all(&header)
+        .context("Failed to write ZIP segment")?;
+
+    let file = File::open(&entry.path).context(format!("Failed to open {:?}", entry.path))?;
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, file);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut crc = Crc::new();
+    let mut size = 0u64;
+    let start = writer.total_written();
+    let mut encoder = DeflateEncoder::new(&mut *writer, flate2::Compression::default());
+
+    loop {
+        let bytes_read = reader
+            .read(&mut buffer)
+            .context(format!("Failed to read from {:?}", entry.path))?;
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        crc.update(&buffer[..bytes_read]);
+        size += bytes_read as u64;
+        encoder
+            .write_all(&buffer[..bytes_read])
+            .context("Failed to write ZIP segment")?;
+        progress(
+            bytes_read as u64,
+            encoder.get_ref().position().0 as usize + 1,
+        );
+    }
+
+    encoder.finish().context("Failed to write ZIP segment")?;
+
+    record.crc = crc.sum();
+    record.size = size;
+    record.compressed_size = writer.total_written() - start;
+
+    if !zip64 && (record.size >= u32::MAX as u64 || record.compressed_size >= u32::MAX as u64) {
+        anyhow::bail!("File grew while it was being archived: {:?}", entry.path);
+    }
+
+    writer.patch(record.disk, record.offset, &local_header(&record, zip64))?;
+
+    Ok(record)
+}
+
+fn local_header(record: &CentralRecord, zip64: bool) -> Vec<u8> {
+    let (time, date) = dos_date_time(record.mtime);
+    let mut extra = timestamp_extra(record.mtime);
+    if zip64 {
+        put_u16(&mut extra, ZIP64_EXTRA_ID);
+        put_u16(&mut extra, 16);
+        put_u64(&mut extra, record.size);
+        put_u64(&mut extra, record.compressed_size);
+    }
+
+    let mut header = Vec::with_capacity(30 + record.name.len() + extra.len());
+    put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+    put_u16(
+        &mut header,
+        if zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        },
+    );
+    put_u16(&mut header, record.flags);
+    put_u16(&mut header, record.method);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, record.crc);
+    if zip64 {
+        put_u32(&mut header, u32::MAX);
+        put_u32(&mut header, u32::MAX);
+    } else {
+        put_u32(&mut header, record.compressed_size as u32);
+        put_u32(&mut header, record.size as u32);
+    }
+    put_u16(&mut header, record.name.len() as u16);
+    put_u16(&mut header, extra.len() as u16);
+    header.extend_from_slice(record.name.as_bytes());
+    header.extend_from_slice(&extra);
+    header
+}
+
+fn central_header(record: &CentralRecord) -> Vec<u8> {
+    let (time, date) = dos_date_time(record.mtime);
+    let mut extra = timestamp_extra(record.mtime);
+    let zip64 = zip64_extra(
+        &mut extra,
+        record.size,
+        record.compressed_size,
+        record.offset,
+    );
+
+    let mut header = Vec::with_capacity(46 + record.name.len() + extra.len());
+    put_u32(&mut header, CENTRAL_HEADER_SIGNATURE);
+    put_u16(&mut header, VERSION_MADE_BY);
+    put_u16(
+        &mut header,
+        if zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        },
+    );
+    put_u16(&mut header, record.flags);
+    put_u16(&mut header, record.method);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, record.crc);
+    put_u32(&mut header, clamp_u32(record.compressed_size));
+    put_u32(&mut header, clamp_u32(record.size));
+    put_u16(&mut header, record.name.len() as u16);
+    put_u16(&mut header, extra.len() as u16);
+    put_u16(&mut header, 0); // comment length
+                             // `SegmentWriter` never writes more than `MAX_SEGMENTS` disks.
+    put_u16(&mut header, record.disk as u16);
+    put_u16(&mut header, 0); // internal attributes
+    put_u32(&mut header, record.external_attributes);
+    put_u32(&mut header, clamp_u32(record.offset));
+    header.extend_from_slice(record.name.as_bytes());
+    header.extend_from_slice(&extra);
+    header
+}
+
+// Appends a zip64 extra field holding whichever values don't fit in 32 bits,
+// in the order the spec requires. Returns whether one was needed.
+fn zip64_extra(extra: &mut Vec<u8>, size: u64, compressed_size: u64, offset: u64) -> bool {
+    let fields: Vec<u64> = [size, compressed_size, offset]
+        .into_iter()
+        .filter(|&value| value >= u32::MAX as u64)
+        .collect();
+
+    if fields.is_empty() {
+        return false;
+    }
+
+    put_u16(extra, ZIP64_EXTRA_ID);
+    put_u16(extra, (fields.len() * 8) as u16);
+    for value in fields {
+        put_u64(extra, value);
+    }
+    true
+}
+
+fn timestamp_extra(mtime: u64) -> Vec<u8> {
+    let mut extra = Vec::with_capacity(9);
+    put_u16(&mut extra, TIMESTAMP_EXTRA_ID);
+    put_u16(&mut extra, 5);
+    extra.push(1); // modification time present
+    put_u32(&mut extra, mtime.min(u32::MAX as u64) as u32);
+    extra
+}
+
+fn write_central_directory(writer: &mut SegmentWriter, records: &[CentralRecord]) -> Result<()> {
+    let mut cd_start = None;
+    let mut cd_size = 0u64;
+    let mut last_disk = writer.position().0;
+    let mut entries_on_last_disk = 0u64;
+
+    for record in records {
+        let header = central_header(record);
+        writer.ensure_room(header.len() as u64)?;
+
+        let (disk, offset) = writer.position();
+        cd_start.get_or_insert((disk, offset));
+        if disk != last_disk {
+            last_disk = disk;
+            entries_on_last_disk = 0;
+        }
+
+        writer
+            .write_all(&header)
+            .context("Failed to write ZIP segment")?;
+        cd_size += header.len() as u64;
+        entries_on_last_disk += 1;
+    }
+
+    let (cd_disk, cd_offset) = cd_start.unwrap_or_else(|| writer.position());
+    let mut end = EndOfCentralDirectory {
+        disk: last_disk,
+        cd_disk,
+        entries_on_disk: entries_on_last_disk,
+        entries: records.len() as u64,
+        cd_size,
+        cd_offset,
+        comment: Vec::new(),
+    };
+
+    // The end records have to share the last segment; if they don't fit in
+    // the current one, the new segment holds no central directory entries.
+    writer.ensure_room(end.encoded_len() as u64)?;
+    let (disk, offset) = writer.position();
+    if disk != last_disk {
+        end.disk = disk;
+        end.entries_on_disk = 0;
+    }
+
+    writer
+        .write_all(&end.encode(offset))
+        .context("Failed to write ZIP segment")
+}
+
+struct EndOfCentralDirectory {
+    disk: u32,
+    cd_disk: u32,
+    entries_on_disk: u64,
+    entries: u64,
+    cd_size: u64,
+    cd_offset: u64,
+    comment: Vec<u8>,
+}
+
+impl EndOfCentralDirectory {
+    fn needs_zip64(&self) -> bool {
+        // Leaves a disk of headroom, as the end records may still move to
+        // the next segment after their size has been computed.
+        self.disk >= 0xFFFE
+            || self.cd_disk >= 0xFFFF
+            || self.entries >= 0xFFFF
+            || self.cd_size >= u32::MAX as u64
+            || self.cd_offset >= u32::MAX as u64
+    }
+
+    fn encoded_len(&self) -> usize {
+        let zip64_len = if self.needs_zip64() {
+            ZIP64_END_OF_CENTRAL_DIR_LEN + ZIP64_LOCATOR_LEN
+        } else {
+            0
+        };
+        zip64_len + END_OF_CENTRAL_DIR_LEN + self.comment.len()
+    }
+
+    // `offset` is where the records start within the last segment.
+    fn encode(&self, offset: u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len());
+
+        if self.needs_zip64() {
+            put_u32(&mut bytes, ZIP64_END_OF_CENTRAL_DIR_SIGNATURE);
+            put_u64(&mut bytes, (ZIP64_END_OF_CENTRAL_DIR_LEN - 12) as u64);
+            put_u16(&mut bytes, VERSION_MADE_BY);
+            put_u16(&mut bytes, VERSION_ZIP64);
+            put_u32(&mut bytes, self.disk);
+            put_u32(&mut bytes, self.cd_disk);
+            put_u64(&mut bytes, self.entries_on_disk);
+            put_u64(&mut bytes, self.entries);
+            put_u64(&mut bytes, self.cd_size);
+            put_u64(&mut bytes, self.cd_offset);
+
+            put_u32(&mut bytes, ZIP64_LOCATOR_SIGNATURE);
+            put_u32(&mut bytes, self.disk);
+            put_u64(&mut bytes, offset);
+            put_u32(&mut bytes, self.disk + 1);
+        }
+
+        put_u32(&mut bytes, END_OF_CENTRAL_DIR_SIGNATURE);
+        put_u16(&mut bytes, self.disk.min(0xFFFF) as u16);
+        put_u16(&mut bytes, self.cd_disk.min(0xFFFF) as u16);
+        put_u16(&mut bytes, self.entries_on_disk.min(0xFFFF) as u16);
+        put_u16(&mut bytes, self.entries.min(0xFFFF) as u16);
+        put_u32(&mut bytes, clamp_u32(self.cd_size));
+        put_u32(&mut bytes, clamp_u32(self.cd_offset));
+        put_u16(&mut bytes, self.comment.len() as u16);
+        bytes.extend_from_slice(&self.comment);
+        bytes
+    }
+}
+
+// Disk numbers are 16-bit in the central directory.
+const MAX_SEGMENTS: usize = 0xFFFF;
+
+// Writes a stream across numbered segments (`.z01`, `.z02`, ...); the last
+// one is renamed to `.zip` by `finish`. Segments are written under temporary
+// names and only appear once the whole archive is complete.
+struct SegmentWriter {
+    zip_path: PathBuf,
+    segment_size: u64,
+    segments: TempSegments,
+    current: BufWriter<File>,
+    position: u64,
+    total_written: u64,
+    durability: Durability,
+}
+
+impl SegmentWriter {
+    fn new(zip_path: &Path, segment_size: u64, durability: Durability) -> Result<Self> {
+        let first = zip_path.with_extension("z01");
+        let file = File::create(temp_path(&first))
+            .context(format!("Failed to create part file: {:?}", first))?;
+
+        let mut writer = Self {
+            zip_path: zip_path.to_path_buf(),
+            segment_size,
+            segments: TempSegments {
+                paths: vec![first],
+                committed: false,
+            },
+            current: BufWriter::with_capacity(BUFFER_SIZE, file),
+            position: 0,
+            total_written: 0,
+            durability,
+        };
+        writer
+            .write_all(&SPLIT_SIGNATURE.to_le_bytes())
+            .context("Failed to write ZIP segment")?;
+        Ok(writer)
+    }
+
+    // Disk number (0-based) and offset within it of the next byte written.
+    fn position(&self) -> (u32, u64) {
+        (self.segments.paths.len() as u32 - 1, self.position)
+    }
+
+    fn total_written(&self) -> u64 {
+        self.total_written
+    }
+
+    // Headers must not span segments: starts a new one unless `len` bytes
+    // still fit in the current one.
+    fn ensure_room(&mut self, len: u64) -> Result<()> {
+        if len > self.segment_size {
+            anyhow::bail!("ZIP header of {} bytes does not fit in a part", len);
+        }
+        if self.position + len > self.segment_size {
+            self.next_segment()?;
+        }
+        Ok(())
+    }
+
+    // Overwrites bytes already written at `offset` of segment `disk`.
+    fn patch(&mut self, disk: u32, offset: u64, bytes: &[u8]) -> Result<()> {
+        self.current.flush().context("Failed to flush part file")?;
+
+        let path = &self.segments.paths[disk as usize];
+        OpenOptions::new()
+            .write(true)
+            .open(temp_path(path))
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(bytes)
+            })
+            .context(format!("Failed to update part file: {:?}", path))
+    }
+
+    fn next_segment(&mut self) -> Result<()> {
+        if self.segments.paths.len() == MAX_SEGMENTS {
+            anyhow::bail!(
+                "A split ZIP can have at most {} parts; use a larger part size",
+                MAX_SEGMENTS
+            );
+        }
+        let path = self
+            .zip_path
+            .with_extension(format!("z{:02}", self.segments.paths.len() + 1));
+        let file = File::create(temp_path(&path))
+            .context(format!("Failed to create part file: {:?}", path))?;
+        self.segments.paths.push(path);
+
+        let finished = std::mem::replace(
+            &mut self.current,
+            BufWriter::with_capacity(BUFFER_SIZE, file),
+        );
+        let file = finished
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush part file")?;
+        if self.durability != Durability::None {
+            file.sync_all().context("Failed to sync part file")?;
+        }
+
+        self.position = 0;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Vec<PathBuf>> {
+        let mut file = self
+            .current
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush part file")?;
+        let mut temp = self.segments;
+        let mut segments = temp.paths.clone();
+
+        // An archive that fit in one segment is an ordinary ZIP, apart from
+        // the marker it starts with.
+        if segments.len() == 1 {
+            file.seek(SeekFrom::Start(0))
+                .and_then(|_| file.write_all(&SINGLE_SEGMENT_SIGNATURE.to_le_bytes()))
+                .context("Failed to write ZIP segment")?;
+        }
+        if self.durability != Durability::None {
+            file.sync_all().context("Failed to sync part file")?;
+        }
+        drop(file);
+
+        let temp_segments: Vec<PathBuf> = segments.iter().map(|path| temp_path(path)).collect();
+        if let Some(last) = segments.last_mut() {
+            *last = self.zip_path.clone();
+        }
+        for (temp, segment) in temp_segments.iter().zip(&segments) {
+            fs::rename(temp, segment)
+                .context(format!("Failed to rename {:?} to {:?}", temp, segment))?;
+        }
+        temp.committed = true;
+        if self.durability == Durability::Directories {
+            sync_dir(parent_dir(&self.zip_path))?;
+        }
+
+        Ok(segments)
+    }
+}
+
+// The segments written so far, whose temporary files are removed again when
+// the archive is never completed, like an uncommitted `AtomicFile`.
+struct TempSegments {
+    paths: Vec<PathBuf>,
+    committed: bool,
+}
+
+impl Drop for TempSegments {
+    fn drop(&mut self) {
+        if !self.committed {
+            for path in &self.paths {
+                let _ = fs::remove_file(temp_path(path));
+            }
+        }
+    }
+}
+
+impl Write for SegmentWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.position == self.segment_size {
+            self.next_segment()
+                .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+        }
+
+        let len = ((self.segment_size - self.position) as usize).min(buf.len());
+        let written = self.current.write(&buf[..len])?;
+        self.position += written as u64;
+        self.total_written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.current.flush()
+    }
+}
+
+// Rebuilds a single ordinary ZIP from a split archive, like `zip -s 0`: the
+// segments are concatenated without the split marker and every offset in the
+// central directory is made relative to the start of the joined file.
+pub fn join_split_zip<F>(
+    first_segment: &Path,
+    output_path: &Path,
+    durability: Durability,
+    rate_limit: &RateLimit,
+    mut progress_callback: F,
+) -> Result<PathBuf>
+where
+    F: FnMut(ProgressInfo),
+{
+    let segments = SegmentSet::open(find_segments(first_segment)?)?;
+    let end = segments.read_end_of_central_directory()?;
+    let total_size = segments.total_size();
+    let total_parts = segments.paths.len();
+
+    let cd_start = segments.raw_offset(end.cd_disk, end.cd_offset)?;
+    let central_directory = segments.read_at(cd_start, end.cd_size as usize)?;
+
+    let output_file =
+        AtomicFile::create(output_path, durability).context("Failed to create output file")?;
+    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, output_file);
+    let mut last_copied = 0;
+
+    segments.copy_range(
+        segments.marker_len,
+        cd_start,
+        &mut writer,
+        &mut |copied, current_part| {
+            rate_limit.take(copied - last_copied);
+            last_copied = copied;
+            progress_callback(ProgressInfo {
+                current_bytes: copied,
+                total_bytes: total_size,
+                current_part,
+                total_parts,
+                message: format!("Joining part {}/{}", current_part, total_parts),
+            });
+        },
+    )?;
+
+    let mut cd = Vec::with_capacity(central_directory.len());
+    let mut position = 0usize;
+    for _ in 0..end.entries {
+        position += rewrite_central_header(&segments, &central_directory[position..], &mut cd)?;
+    }
+
+    let cd_offset = cd_start - segments.marker_len;
+    let rebuilt_end = EndOfCentralDirectory {
+        disk: 0,
+        cd_disk: 0,
+        entries_on_disk: end.entries,
+        entries: end.entries,
+        cd_size: cd.len() as u64,
+        cd_offset,
+        comment: end.comment,
+    };
+
+    writer
+        .write_all(&cd)
+        .and_then(|_| writer.write_all(&rebuilt_end.encode(cd_offset + cd.len() as u64)))
+        .context("Failed to write to output file")?;
+    atomic::commit_buffered(writer).context("Failed to write output file")?;
+
+    progress_callback(ProgressInfo {
+        current_bytes: total_size,
+        total_bytes: total_size,
+        current_part: total_parts,
+        total_parts,
+        message: "Join complete!".to_string(),
+    });
+
+    Ok(output_path.to_path_buf())
+}
+
+// Reads every entry of a split ZIP back and checks its data against the CRC
+// and size in the central directory.
+pub fn verify_split_zip<F>(first_segment: &Path, mut progress_callback: F) -> Result<()>
+where
+    F: FnMut(ProgressInfo),
+{
+    let segments = SegmentSet::open(find_segments(first_segment)?)?;
+    let end = segments.read_end_of_central_directory()?;
+    let total_size = segments.total_size();
+    let total_parts = segments.paths.len();
+
+    let cd_start = segments.raw_offset(end.cd_disk, end.cd_offset)?;
+    let central_directory = segments.read_at(cd_start, end.cd_size as usize)?;
+    let mut position = 0usize;
+
+    for _ in 0..end.entries {
+        let record = &central_directory[position..];
+        let entry = read_central_header(record)?;
+        let name = String::from_utf8_lossy(&record[46..46 + entry.name_len]).into_owned();
+        position += entry.len;
+
+        let local_start = segments.raw_offset(entry.disk, entry.offset)?;
+        let local = segments.read_at(local_start, 30)?;
+        if le_u32(&local, 0) != LOCAL_HEADER_SIGNATURE {
+            anyhow::bail!("Corrupt local header for {:?} in split ZIP", name);
+        }
+        let data_start = local_start + 30 + le_u16(&local, 26) as u64 + le_u16(&local, 28) as u64;
+
+        let mut check = CrcCheck {
+            crc: Crc::new(),
+            size: 0,
+        };
+        let mut copy_progress = |copied: u64, current_part: usize| {
+            progress_callback(ProgressInfo {
+                current_bytes: data_start + copied,
+                total_bytes: total_size,
+                current_part,
+                total_parts,
+                message: format!("Checking part {}/{}", current_part, total_parts),
+            })
+        };
+        let data_end = data_start + entry.compressed_size;
+        match le_u16(record, 10) {
+            METHOD_STORED => {
+                segments.copy_range(data_start, data_end, &mut check, &mut copy_progress)?
+            }
+            METHOD_DEFLATED => {
+                let mut decoder = flate2::write::DeflateDecoder::new(&mut check);
+                segments.copy_range(data_start, data_end, &mut decoder, &mut copy_progress)?;
+                decoder.finish().context(format!(
+                    "Corrupt compressed data for {:?} in split ZIP",
+                    name
+                ))?;
+            }
+            method => anyhow::bail!(
+                "Unsupported compression method {} for {:?} in split ZIP",
+                method,
+                name
+            ),
+        }
+
+        if check.crc.sum() != le_u32(record, 16) || check.size != entry.size {
+            anyhow::bail!("{:?} does not match its checksum in the split ZIP", name);
+        }
+    }
+
+    Ok(())
+}
+
+// Counts and checksums the data written to it.
+struct CrcCheck {
+    crc: Crc,
+    size: u64,
+}
+
+impl Write for CrcCheck {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.crc.update(buf);
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// One central directory record with its zip64 values resolved.
+struct CentralEntry {
+    len: usize,
+    name_len: usize,
+    size: u64,
+    compressed_size: u64,
+    disk: u32,
+    offset: u64,
+    // Extra fields other than zip64, still encoded.
+    other_extra: Vec<u8>,
+}
+
+fn read_central_header(record: &[u8]) -> Result<CentralEntry> {
+    if record.len() < 46 || le_u32(record, 0) != CENTRAL_HEADER_SIGNATURE {
+        anyhow::bail!("Corrupt central directory in split ZIP");
+    }
+
+    let name_len = le_u16(record, 28) as usize;
+    let extra_len = le_u16(record, 30) as usize;
+    let comment_len = le_u16(record, 32) as usize;
+    let len = 46 + name_len + extra_len + comment_len;
+    if record.len() < len {
+        anyhow::bail!("Corrupt central directory in split ZIP");
+    }
+
+    let mut size = le_u32(record, 24) as u64;
+    let mut compressed_size = le_u32(record, 20) as u64;
+    let mut disk = le_u16(record, 34) as u32;
+    let mut offset = le_u32(record, 42) as u64;
+
+    // Zip64 values are present only for the fields saturated above, in this
+    // order.
+    let extra = &record[46 + name_len..46 + name_len + extra_len];
+    let mut other_extra = Vec::with_capacity(extra.len());
+    for (id, data) in extra_fields(extra) {
+        if id != ZIP64_EXTRA_ID {
+            put_u16(&mut other_extra, id);
+            put_u16(&mut other_extra, data.len() as u16);
+            other_extra.extend_from_slice(data);
+            continue;
+        }
+
+        let mut values = data.chunks_exact(8).map(|v| le_u64(v, 0));
+        if size == u32::MAX as u64 {
+            size = values.next().context("Corrupt zip64 field")?;
+        }
+        if compressed_size == u32::MAX as u64 {
+            compressed_size = values.next().context("Corrupt zip64 field")?;
+        }
+        if offset == u32::MAX as u64 {
+            offset = values.next().context("Corrupt zip64 field")?;
+        }
+        if disk == 0xFFFF {
+            let index = data.len() / 8 * 8;
+            disk = data
+                .get(index..index + 4)
+                .map(|v| le_u32(v, 0))
+                .context("Corrupt zip64 field")?;
+        }
+    }
+
+    Ok(CentralEntry {
+        len,
+        name_len,
+        size,
+        compressed_size,
+        disk,
+        offset,
+        other_extra,
+    })
+}
+
+// Copies one central directory record into `out` with its local header offset
+// made absolute and its disk number cleared. Returns the record's length.
+fn rewrite_central_header(
+    segments: &SegmentSet,
+    record: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<usize> {
+    let CentralEntry {
+        len,
+        name_len,
+        size,
+        compressed_size,
+        disk,
+        offset,
+        mut other_extra,
+    } = read_central_header(record)?;
+    let extra_len = le_u16(record, 30) as usize;
+
+    let offset = segments.raw_offset(disk, offset)? - segments.marker_len;
+    let zip64 = zip64_extra(&mut other_extra, size, compressed_size, offset);
+
+    let mut header = record[..46].to_vec();
+    if zip64 {
+        header[6..8].copy_from_slice(&VERSION_ZIP64.max(le_u16(record, 6)).to_le_bytes());
+    }
+    header[20..24].copy_from_slice(&clamp_u32(compressed_size).to_le_bytes());
+    header[24..28].copy_from_slice(&clamp_u32(size).to_le_bytes());
+    header[30..32].copy_from_slice(&(other_extra.len() as u16).to_le_bytes());
+    header[34..36].copy_from_slice(&0u16.to_le_bytes());
+    header[42..46].copy_from_slice(&clamp_u32(offset).to_le_bytes());
+
+    out.extend_from_slice(&header);
+    out.extend_from_slice(&record[46..46 + name_len]);
+    out.extend_from_slice(&other_extra);
+    out.extend_from_slice(&record[46 + name_len + extra_len..len]);
+
+    Ok(len)
+}
+
+fn extra_fields(mut extra: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut fields = Vec::new();
+
+    while extra.len() >= 4 {
+        let id = le_u16(extra, 0);
+        let len = (le_u16(extra, 2) as usize).min(extra.len() - 4);
+        fields.push((id, &extra[4..4 + len]));
+        extra = &extra[4 + len..];
+    }
+
+    fields
+}
+
+// The segments of a split ZIP addressed as one concatenated stream.
+struct SegmentSet {
+    paths: Vec<PathBuf>,
+    starts: Vec<u64>,
+    sizes: Vec<u64>,
+    marker_len: u64,
+}
+
+impl SegmentSet {
+    fn open(paths: Vec<PathBuf>) -> Result<Self> {
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut sizes = Vec::with_capacity(paths.len());
+        let mut total = 0u64;
+
+        for path in &paths {
+            let size = fs::metadata(path)
+                .context(format!("Failed to get metadata: {:?}", path))?
+                .len();
+            starts.push(total);
+            sizes.push(size);
+            total += size;
+        }
+
+        let mut set = Self {
+            paths,
+            starts,
+            sizes,
+            marker_len: 0,
+        };
+
+        if set.total_size() >= 4 {
+            let marker = le_u32(&set.read_at(0, 4)?, 0);
+            if marker == SPLIT_SIGNATURE || marker == SINGLE_SEGMENT_SIGNATURE {
+                set.marker_len = 4;
+            }
+        }
+
+        Ok(set)
+    }
+
+    fn total_size(&self) -> u64 {
+        self.sizes.iter().sum()
+    }
+
+    fn raw_offset(&self, disk: u32, offset: u64) -> Result<u64> {
+        let start = self
+            .starts
+            .get(disk as usize)
+            .context(format!("Split ZIP refers to missing part {}", disk + 1))?;
+        Ok(start + offset)
+    }
+
+    fn read_at(&self, position: u64, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        self.copy_range(position, position + len as u64, &mut bytes, &mut |_, _| {})?;
+        Ok(bytes)
+    }
+
+    fn copy_range(
+        &self,
+        start: u64,
+        end: u64,
+        writer: &mut dyn Write,
+        progress: &mut dyn FnMut(u64, usize),
+    ) -> Result<()> {
+        if end > self.total_size() || start > end {
+            anyhow::bail!("Split ZIP is truncated");
+        }
+
+        let mut buffer = vec![0u8; BUFFER_SIZE.min((end - start) as usize)];
+        let mut position = start;
+
+        for (index, path) in self.paths.iter().enumerate() {
+            let segment_end = self.starts[index] + self.sizes[index];
+            if position >= end {
+                break;
+            }
+            if position >= segment_end {
+                continue;
+            }
+
+            let mut file =
+                File::open(path).context(format!("Failed to open part file: {:?}", path))?;
+            file.seek(SeekFrom::Start(position - self.starts[index]))
+                .context(format!("Failed to read from part file: {:?}", path))?;
+            let mut remaining = segment_end.min(end) - position;
+
+            while remaining > 0 {
+                let to_read = (buffer.len() as u64).min(remaining) as usize;
+                file.read_exact(&mut buffer[..to_read])
+                    .context(format!("Failed to read from part file: {:?}", path))?;
+                writer
+                    .write_all(&buffer[..to_read])
+                    .context("Failed to write to output file")?;
+
+                position += to_read as u64;
+                remaining -= to_read as u64;
+                progress(position - start, index + 1);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_end_of_central_directory(&self) -> Result<EndOfCentralDirectory> {
+        let total = self.total_size();
+        let tail_len = total.min((END_OF_CENTRAL_DIR_LEN + MAX_COMMENT_LEN) as u64);
+        let tail = self.read_at(total - tail_len, tail_len as usize)?;
+
+        let position = (0..=tail.len().saturating_sub(END_OF_CENTRAL_DIR_LEN))
+            .rev()
+            .find(|&i| {
+                le_u32(&tail, i) == END_OF_CENTRAL_DIR_SIGNATURE
+                    && i + END_OF_CENTRAL_DIR_LEN + le_u16(&tail, i + 20) as usize == tail.len()
+            })
+            .context("No end of central directory record found; not a ZIP archive")?;
+        let record = &tail[position..];
+
+        let mut end = EndOfCentralDirectory {
+            disk: le_u16(record, 4) as u32,
+            cd_disk: le_u16(record, 6) as u32,
+            entries_on_disk: le_u16(record, 8) as u64,
+            entries: le_u16(record, 10) as u64,
+            cd_size: le_u32(record, 12) as u64,
+            cd_offset: le_u32(record, 16) as u64,
+            comment: record[END_OF_CENTRAL_DIR_LEN..].to_vec(),
+        };
+
+        let eocd_position = total - tail_len + position as u64;
+        if eocd_position >= ZIP64_LOCATOR_LEN as u64 {
+            let locator =
+                self.read_at(eocd_position - ZIP64_LOCATOR_LEN as u64, ZIP64_LOCATOR_LEN)?;
+            if le_u32(&locator, 0) == ZIP64_LOCATOR_SIGNATURE {
+                let zip64_position = self.raw_offset(le_u32(&locator, 4), le_u64(&locator, 8))?;
+                let record = self.read_at(zip64_position, ZIP64_END_OF_CENTRAL_DIR_LEN)?;
+                if le_u32(&record, 0) != ZIP64_END_OF_CENTRAL_DIR_SIGNATURE {
+                    anyhow::bail!("Corrupt zip64 end of central directory record");
+                }
+                end.disk = le_u32(&record, 16);
+                end.cd_disk = le_u32(&record, 20);
+                end.entries_on_disk = le_u64(&record, 24);
+                end.entries = le_u64(&record, 32);
+                end.cd_size = le_u64(&record, 40);
+                end.cd_offset = le_u64(&record, 48);
+            }
+        }
+
+        if end.disk as usize + 1 != self.paths.len() {
+            anyhow::bail!(
+                "Split ZIP has {} parts but its directory expects {}",
+                self.paths.len(),
+                end.disk + 1
+            );
+        }
+
+        Ok(end)
+    }
+}
+
+// MS-DOS date and time fields, in UTC; the extended timestamp field carries
+// the exact time for tools that understand it.
+fn dos_date_time(unix_secs: u64) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = civil_from_unix(unix_secs);
+
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    let time = (hour << 11) | (minute << 5) | (second / 2);
+    let date = (((year - 1980).min(127) as u32) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+fn clamp_u32(value: u64) -> u32 {
+    value.min(u32::MAX as u64) as u32
+}
+
+fn put_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{self, SplitOptions};
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2024-02-29 13:45:30 UTC
+        let (time, date) = dos_date_time(1_709_214_330);
+        assert_eq!(time, (13 << 11) | (45 << 5) | 15);
+        assert_eq!(date, (44 << 9) | (2 << 5) | 29);
+        assert_eq!(dos_date_time(0), (0, (1 << 5) | 1));
+    }
+
+    #[test]
+    fn test_split_zip_joins_into_ordinary_zip() {
+        let root = Path::new("test_split_zip");
+        let _ = fs::remove_dir_all(root);
+        fs::create_dir_all(root.join("nested")).unwrap();
+        let text: Vec<u8> = (0..40_000)
+            .flat_map(|i| format!("line {}\n", i).into_bytes())
+            .collect();
+        // Poorly compressible, so the archive needs several segments.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..200_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        fs::write(root.join("text.txt"), &text).unwrap();
+        fs::write(root.join("nested/noise.bin"), &noise).unwrap();
+
+        let options = SplitOptions {
+            zip: true,
+            ..SplitOptions::new(MIN_SEGMENT_SIZE)
+        };
+        let segments = core::split_file_with_options(root, &options, |_| {}).unwrap();
+        assert!(segments.len() > 2);
+        assert_eq!(segments[0], PathBuf::from("test_split_zip.z01"));
+        assert_eq!(
+            segments.last().unwrap(),
+            &PathBuf::from("test_split_zip.zip")
+        );
+        for segment in &segments {
+            assert!(fs::metadata(segment).unwrap().len() <= MIN_SEGMENT_SIZE);
+        }
+        assert_eq!(&fs::read(&segments[0]).unwrap()[..4], b"PK\x07\x08");
+
+        let output = Path::new("test_split_zip_joined.zip");
+        core::join_files(&segments[0], output, |_| {}).unwrap();
+
+        let mut archive = zip::ZipArchive::new(File::open(output).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut data = Vec::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            data
+        };
+        assert_eq!(read("test_split_zip/text.txt"), text);
+        assert_eq!(read("test_split_zip/nested/noise.bin"), noise);
+        assert!(archive.by_name("test_split_zip/nested/").unwrap().is_dir());
+
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_file(output).unwrap();
+        for segment in segments {
+            fs::remove_file(segment).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_single_segment_split_zip_joins() {
+        let input = Path::new("test_single_segment.txt");
+        fs::write(input, b"small enough for one segment\n".repeat(100)).unwrap();
+
+        let options = SplitOptions {
+            zip: true,
+            ..SplitOptions::new(MIN_SEGMENT_SIZE)
+        };
+        let segments = core::split_file_with_options(input, &options, |_| {}).unwrap();
+        assert_eq!(segments, vec![PathBuf::from("test_single_segment.txt.zip")]);
+        assert_eq!(&fs::read(&segments[0]).unwrap()[..4], b"PK00");
+        assert!(is_split_zip(&segments[0]));
+
+        let output = Path::new("test_single_segment_joined.zip");
+        core::join_files(&segments[0], output, |_| {}).unwrap();
+        let mut archive = zip::ZipArchive::new(File::open(output).unwrap()).unwrap();
+        let mut data = Vec::new();
+        archive
+            .by_name("test_single_segment.txt")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, fs::read(input).unwrap());
+
+        // An ordinary ZIP is not taken for a split one.
+        assert!(!is_split_zip(output));
+
+        fs::remove_file(input).unwrap();
+        fs::remove_file(output).unwrap();
+        fs::remove_file(&segments[0]).unwrap();
+    }
+
+    #[test]
+    fn test_verify_split_zip_finds_damaged_segments() {
+        let input = Path::new("test_verify_zip.bin");
+        // Incompressible, so the archive spans several segments.
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..3 * MIN_SEGMENT_SIZE)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect();
+        fs::write(input, &data).unwrap();
+
+        let options = SplitOptions {
+            zip: true,
+            remove_source: true,
+            ..SplitOptions::new(MIN_SEGMENT_SIZE)
+        };
+        let segments = core::split_file_with_options(input, &options, |_| {}).unwrap();
+        assert!(segments.len() > 2);
+        assert!(!input.exists());
+        let last = segments.last().unwrap();
+        verify_split_zip(last, |_| {}).unwrap();
+
+        let mut damaged = fs::read(&segments[1]).unwrap();
+        damaged[1000] ^= 0xFF;
+        fs::write(&segments[1], damaged).unwrap();
+        assert!(verify_split_zip(last, |_| {}).is_err());
+
+        for segment in &segments {
+            fs::remove_file(segment).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_unfinished_segments_are_removed() {
+        let zip_path = Path::new("test_unfinished.zip");
+        let mut writer = SegmentWriter::new(zip_path, MIN_SEGMENT_SIZE, Durability::None).unwrap();
+        writer.write_all(&vec![7u8; 200_000]).unwrap();
+        assert!(temp_path(Path::new("test_unfinished.z03")).exists());
+
+        drop(writer);
+        for number in 1..=4 {
+            assert!(!temp_path(Path::new(&format!("test_unfinished.z{:02}", number))).exists());
+        }
+    }
+}